@@ -0,0 +1,89 @@
+use crate::{EXIT_INVALID, EXIT_OK, EXIT_USAGE};
+use cptv_decoder::reader::CptvReader;
+use cptv_encoder::crop::{crop_clip, CropOptions};
+use cptv_encoder::encode_clip;
+use cptv_shared::v2::tracking::BoundingBox;
+use std::fs::File;
+use std::io::BufReader;
+
+pub fn run(args: &[String]) -> i32 {
+    let mut options = CropOptions::default();
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--region" => match args.next().and_then(|region| parse_region(region)) {
+                Some(region) => options.region = Some(region),
+                None => {
+                    eprintln!("--region expects a rectangle like 10,20,64x48 (x,y,widthxheight)");
+                    return EXIT_USAGE;
+                }
+            },
+            "--downscale" => match args.next().and_then(|factor| factor.parse().ok()) {
+                Some(factor) if factor > 0 => options.downscale = factor,
+                _ => {
+                    eprintln!("--downscale expects a positive integer");
+                    return EXIT_USAGE;
+                }
+            },
+            _ if arg.starts_with("--") => {
+                eprintln!("Unknown option {}", arg);
+                return EXIT_USAGE;
+            }
+            _ => files.push(arg),
+        }
+    }
+    let (input, output) = match files.as_slice() {
+        [input, output] => (input, output),
+        _ => {
+            eprintln!("Expected an input CPTV file and an output file");
+            return EXIT_USAGE;
+        }
+    };
+
+    let file = match File::open(input) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return EXIT_USAGE;
+        }
+    };
+    let result = CptvReader::new(BufReader::new(file))
+        .and_then(|reader| reader.into_clip())
+        .and_then(|clip| crop_clip(clip, options));
+    let clip = match result {
+        Ok(clip) => clip,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return EXIT_INVALID;
+        }
+    };
+    if let Err(e) = std::fs::write(output, encode_clip(&clip)) {
+        eprintln!("{}: {}", output, e);
+        return EXIT_USAGE;
+    }
+    println!(
+        "{}: {} frames at {}x{}",
+        output,
+        clip.frames.len(),
+        clip.header.width,
+        clip.header.height
+    );
+    EXIT_OK
+}
+
+fn parse_region(region: &str) -> Option<BoundingBox> {
+    let mut parts = region.split(',');
+    let x = parts.next()?.parse().ok()?;
+    let y = parts.next()?.parse().ok()?;
+    let (width, height) = parts.next()?.split_once('x')?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(BoundingBox {
+        x,
+        y,
+        width: width.parse().ok()?,
+        height: height.parse().ok()?,
+    })
+}
@@ -0,0 +1,95 @@
+use crate::{EXIT_INVALID, EXIT_OK, EXIT_USAGE};
+use cptv_decoder::verify::verify_roundtrip;
+use cptv_encoder::metadata::{edit_header, HeaderEdits};
+use std::str::FromStr;
+
+pub fn run(args: &[String]) -> i32 {
+    let mut edits = HeaderEdits::default();
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let ok = match arg.as_str() {
+            "--device-name" => args
+                .next()
+                .map(|name| edits.device_name = Some(name.clone()))
+                .is_some(),
+            "--device-id" => parse_into(args.next(), &mut edits.device_id),
+            "--latitude" => parse_into(args.next(), &mut edits.latitude),
+            "--longitude" => parse_into(args.next(), &mut edits.longitude),
+            "--altitude" => parse_into(args.next(), &mut edits.altitude),
+            "--accuracy" => parse_into(args.next(), &mut edits.accuracy),
+            "--loc-timestamp" => parse_into(args.next(), &mut edits.loc_timestamp),
+            "--timestamp-offset" => {
+                let mut seconds: Option<f64> = None;
+                let ok = parse_into(args.next(), &mut seconds);
+                edits.timestamp_offset = seconds.map(|seconds| (seconds * 1_000_000.0).round() as i64);
+                ok
+            }
+            _ if arg.starts_with("--") => {
+                eprintln!("Unknown option {}", arg);
+                return EXIT_USAGE;
+            }
+            _ => {
+                files.push(arg);
+                true
+            }
+        };
+        if !ok {
+            eprintln!("{} expects a value", arg);
+            return EXIT_USAGE;
+        }
+    }
+    let (input, output) = match files.as_slice() {
+        [input] => (input, input),
+        [input, output] => (input, output),
+        _ => {
+            eprintln!("Expected a CPTV file, and optionally an output file");
+            return EXIT_USAGE;
+        }
+    };
+    if edits.is_empty() {
+        eprintln!("Nothing to change");
+        return EXIT_USAGE;
+    }
+
+    let file = match std::fs::read(input) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return EXIT_USAGE;
+        }
+    };
+    let edited = match edit_header(&file, &edits) {
+        Ok(edited) => edited,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return EXIT_INVALID;
+        }
+    };
+    // Nothing but the fields we were asked to change should differ.
+    let report = verify_roundtrip(&file, &edited);
+    if !report.is_identical_except(&edits.changed_fields()) {
+        eprintln!("{}: not written, the edited file doesn't match: {}", input, report);
+        return EXIT_INVALID;
+    }
+    // Write alongside and rename, so an interrupted edit can't leave the original half written.
+    let temp = format!("{}.tmp", output);
+    if let Err(e) = std::fs::write(&temp, edited).and_then(|_| std::fs::rename(&temp, output)) {
+        eprintln!("{}: {}", output, e);
+        let _ = std::fs::remove_file(&temp);
+        return EXIT_USAGE;
+    }
+    println!("{}: header updated", output);
+    EXIT_OK
+}
+
+// Parses an option's value into `field`, returning false if it is missing or malformed.
+fn parse_into<T: FromStr>(value: Option<&String>, field: &mut Option<T>) -> bool {
+    match value.and_then(|value| value.parse().ok()) {
+        Some(value) => {
+            *field = Some(value);
+            true
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,47 @@
+use crate::{EXIT_INVALID, EXIT_OK, EXIT_USAGE};
+use cptv_encoder::encode_clip;
+use cptv_shared::v2::types::Clip;
+
+pub fn run(args: &[String]) -> i32 {
+    let (input, output) = match args {
+        [input, output] if !input.starts_with("--") && !output.starts_with("--") => (input, output),
+        _ => {
+            eprintln!("Expected an input JSON file and an output CPTV file");
+            return EXIT_USAGE;
+        }
+    };
+
+    let json = match std::fs::read_to_string(input) {
+        Ok(json) => json,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return EXIT_USAGE;
+        }
+    };
+    let clip: Clip = match serde_json::from_str(&json) {
+        Ok(clip) => clip,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return EXIT_INVALID;
+        }
+    };
+    let (width, height) = (clip.header.width as usize, clip.header.height as usize);
+    let mismatched = clip
+        .background
+        .iter()
+        .chain(clip.frames.iter())
+        .position(|frame| frame.image_data.width() != width || frame.image_data.height() != height);
+    if let Some(index) = mismatched {
+        eprintln!(
+            "{}: frame #{} doesn't match the {}x{} header",
+            input, index, width, height
+        );
+        return EXIT_INVALID;
+    }
+    if let Err(e) = std::fs::write(output, encode_clip(&clip)) {
+        eprintln!("{}: {}", output, e);
+        return EXIT_USAGE;
+    }
+    println!("{}: {} frames", output, clip.frames.len());
+    EXIT_OK
+}
@@ -0,0 +1,36 @@
+use crate::{EXIT_INVALID, EXIT_OK, EXIT_USAGE};
+use cptv_encoder::encode_clip;
+use cptv_encoder::numpy::read_npz;
+use std::fs::File;
+use std::io::BufReader;
+
+pub fn run(args: &[String]) -> i32 {
+    let (input, output) = match args {
+        [input, output] if !input.starts_with("--") && !output.starts_with("--") => (input, output),
+        _ => {
+            eprintln!("Expected an input .npz file and an output CPTV file");
+            return EXIT_USAGE;
+        }
+    };
+
+    let file = match File::open(input) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return EXIT_USAGE;
+        }
+    };
+    let clip = match read_npz(BufReader::new(file)) {
+        Ok(clip) => clip,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return EXIT_INVALID;
+        }
+    };
+    if let Err(e) = std::fs::write(output, encode_clip(&clip)) {
+        eprintln!("{}: {}", output, e);
+        return EXIT_USAGE;
+    }
+    println!("{}: {} frames", output, clip.frames.len());
+    EXIT_OK
+}
@@ -0,0 +1,107 @@
+mod crop;
+mod edit_header;
+mod from_json;
+mod from_npz;
+mod merge;
+mod recompress;
+mod redact;
+mod resample;
+mod telemetry;
+mod thumbnail;
+mod to_json;
+mod to_npz;
+mod validate;
+
+use std::process::exit;
+
+// Exit codes shared by all subcommands.
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_INVALID: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+
+const USAGE: &str = "Usage: cptv <command> [options] <files...>
+
+Commands:
+  validate [--strict] [--json] <files...>
+      Check CPTV files for conformance.  Exits with 0 if all files are valid,
+      1 if any file has errors (or warnings, with --strict), and 2 on usage or IO errors.
+
+  thumbnail [--peak] [--crop <width>x<height>] [--colour-map <name>] [--scale <n>] <file> <output.png>
+      Render a representative frame to a PNG: the frame with the most activity, or with
+      --peak the hottest frame.  --crop centres a window on the hottest region.  Colour maps
+      are greyscale, ironbow (the default) and viridis.
+
+  merge [--keep-gaps] <files...> <output.cptv>
+      Join consecutive recordings from the same camera into one file, using the first file's
+      header.  Time runs on continuously from one recording to the next, or with --keep-gaps
+      includes the time that passed between them.  The first frame of each later recording is
+      an intra frame, which older decoders can't read.
+
+  resample --fps <n> [--average] <file> <output.cptv>
+      Reduce the frame rate of a clip, keeping one frame per period of 1/n seconds.  With
+      --average, each kept frame is the average of the frames in its period.
+
+  crop [--region <x>,<y>,<width>x<height>] [--downscale <n>] <file> <output.cptv>
+      Crop every frame to a rectangle and/or scale it down by an integer factor, averaging
+      each n x n block of pixels.
+
+  recompress [--predictor snake|med] <file> <output.cptv>
+      Re-encode every frame of a clip, by default with the predictor it already has.  The MED
+      predictor usually compresses noisy or busy scenes better, but files that use it can't
+      be read by decoders that predate it.
+
+  edit-header [--device-name <name>] [--device-id <id>] [--latitude <deg>] [--longitude <deg>]
+              [--altitude <m>] [--accuracy <m>] [--loc-timestamp <value>]
+              [--timestamp-offset <seconds>] <file> [<output.cptv>]
+      Change header fields without touching the frames.  Edits the file in place unless an
+      output file is given.  The result is decoded and checked against the original first, and
+      nothing is written unless only the requested fields differ.
+
+  redact [--grid <degrees>] [--remove-location] [--strip-device] <file> <output.cptv>
+      Coarsen the location to a grid (0.01 degrees by default), or remove it altogether, and
+      optionally remove the device name, id and serial number, for sharing recordings.
+
+  to-json [--base64] <file> [<output.json>]
+      Write a clip out as JSON, with the pixels of each frame as an array of rows, or with
+      --base64 as base64 encoded little endian u16s.  Writes to stdout without an output file.
+
+  from-json <input.json> <output.cptv>
+      Encode a clip from its JSON form back into a CPTV file.
+
+  to-npz <file> <output.npz>
+      Export a clip for NumPy: the frames as an N x height x width uint16 array, per-frame
+      time_on, last_ffc_time, frame_temp_c, last_ffc_temp_c and is_background arrays, and the
+      header as a JSON string.
+
+  from-npz <input.npz> <output.cptv>
+      Encode a CPTV file from a .npz archive laid out as to-npz writes it.
+
+  telemetry [--output <file.csv>] <files or directories...>
+      Write a CSV row per frame with its file, time_on, frame_temp_c, last_ffc_temp_c,
+      last_ffc_time, bit_width, frame_size and min/max/mean pixel values.  Directories are
+      searched for .cptv files.  Writes to stdout without --output.
+";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let code = match args.first().map(|command| command.as_str()) {
+        Some("validate") => validate::run(&args[1..]),
+        Some("thumbnail") => thumbnail::run(&args[1..]),
+        Some("merge") => merge::run(&args[1..]),
+        Some("resample") => resample::run(&args[1..]),
+        Some("crop") => crop::run(&args[1..]),
+        Some("recompress") => recompress::run(&args[1..]),
+        Some("edit-header") => edit_header::run(&args[1..]),
+        Some("redact") => redact::run(&args[1..]),
+        Some("to-json") => to_json::run(&args[1..]),
+        Some("from-json") => from_json::run(&args[1..]),
+        Some("to-npz") => to_npz::run(&args[1..]),
+        Some("from-npz") => from_npz::run(&args[1..]),
+        Some("telemetry") => telemetry::run(&args[1..]),
+        _ => {
+            eprint!("{}", USAGE);
+            EXIT_USAGE
+        }
+    };
+    exit(code);
+}
@@ -0,0 +1,64 @@
+use crate::{EXIT_INVALID, EXIT_OK, EXIT_USAGE};
+use cptv_decoder::reader::CptvReader;
+use cptv_encoder::encode_clip;
+use cptv_encoder::merge::{merge_clips, MergeOptions};
+use std::fs::File;
+use std::io::BufReader;
+
+pub fn run(args: &[String]) -> i32 {
+    let mut options = MergeOptions::default();
+    let mut files = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--keep-gaps" => options.keep_gaps = true,
+            _ if arg.starts_with("--") => {
+                eprintln!("Unknown option {}", arg);
+                return EXIT_USAGE;
+            }
+            _ => files.push(arg),
+        }
+    }
+    let (output, inputs) = match files.split_last() {
+        Some((output, inputs)) if inputs.len() >= 2 => (output, inputs),
+        _ => {
+            eprintln!("Expected at least two input CPTV files and an output file");
+            return EXIT_USAGE;
+        }
+    };
+
+    let mut clips = Vec::new();
+    for input in inputs {
+        let file = match File::open(input) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("{}: {}", input, e);
+                return EXIT_USAGE;
+            }
+        };
+        match CptvReader::new(BufReader::new(file)).and_then(|reader| reader.into_clip()) {
+            Ok(clip) => clips.push(clip),
+            Err(e) => {
+                eprintln!("{}: {}", input, e);
+                return EXIT_INVALID;
+            }
+        }
+    }
+    let merged = match merge_clips(clips, options) {
+        Ok(merged) => merged,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_INVALID;
+        }
+    };
+    if let Err(e) = std::fs::write(output, encode_clip(&merged)) {
+        eprintln!("{}: {}", output, e);
+        return EXIT_USAGE;
+    }
+    println!(
+        "{}: merged {} clips, {} frames",
+        output,
+        inputs.len(),
+        merged.frames.len()
+    );
+    EXIT_OK
+}
@@ -0,0 +1,71 @@
+use crate::{EXIT_INVALID, EXIT_OK, EXIT_USAGE};
+use cptv_decoder::reader::CptvReader;
+use cptv_decoder::verify::verify_roundtrip;
+use cptv_encoder::{encode_clip_with_options, EncodeOptions};
+use cptv_shared::v2::types::SpatialPredictor;
+
+pub fn run(args: &[String]) -> i32 {
+    let mut options = EncodeOptions::default();
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--predictor" => match args.next().map(|predictor| predictor.as_str()) {
+                Some("snake") => options.predictor = Some(SpatialPredictor::Snake),
+                Some("med") => options.predictor = Some(SpatialPredictor::Med),
+                _ => {
+                    eprintln!("--predictor expects snake or med");
+                    return EXIT_USAGE;
+                }
+            },
+            _ if arg.starts_with("--") => {
+                eprintln!("Unknown option {}", arg);
+                return EXIT_USAGE;
+            }
+            _ => files.push(arg),
+        }
+    }
+    let (input, output) = match files.as_slice() {
+        [input, output] => (input, output),
+        _ => {
+            eprintln!("Expected an input CPTV file and an output file");
+            return EXIT_USAGE;
+        }
+    };
+
+    let file = match std::fs::read(input) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return EXIT_USAGE;
+        }
+    };
+    let clip = match CptvReader::new(&file[..]).and_then(|reader| reader.into_clip()) {
+        Ok(clip) => clip,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return EXIT_INVALID;
+        }
+    };
+    let encoded = encode_clip_with_options(&clip, &options);
+    let report = verify_roundtrip(&file, &encoded);
+    if !report.is_identical() {
+        eprintln!(
+            "{}: not written, the recompressed file doesn't match: {}",
+            input, report
+        );
+        return EXIT_INVALID;
+    }
+    if let Err(e) = std::fs::write(output, &encoded) {
+        eprintln!("{}: {}", output, e);
+        return EXIT_USAGE;
+    }
+    println!(
+        "{}: {} frames, {} bytes (was {})",
+        output,
+        clip.frames.len(),
+        encoded.len(),
+        file.len()
+    );
+    EXIT_OK
+}
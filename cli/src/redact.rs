@@ -0,0 +1,57 @@
+use crate::{EXIT_INVALID, EXIT_OK, EXIT_USAGE};
+use cptv_encoder::redact::{redact, RedactOptions};
+
+pub fn run(args: &[String]) -> i32 {
+    let mut options = RedactOptions::default();
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--grid" => match args.next().and_then(|grid| grid.parse().ok()) {
+                Some(grid) if grid > 0.0 => options.grid = Some(grid),
+                _ => {
+                    eprintln!("--grid expects a positive number of degrees, e.g. 0.01");
+                    return EXIT_USAGE;
+                }
+            },
+            "--remove-location" => options.grid = None,
+            "--strip-device" => options.strip_device = true,
+            _ if arg.starts_with("--") => {
+                eprintln!("Unknown option {}", arg);
+                return EXIT_USAGE;
+            }
+            _ => files.push(arg),
+        }
+    }
+    let (input, output) = match files.as_slice() {
+        [input, output] => (input, output),
+        _ => {
+            eprintln!("Expected an input CPTV file and an output file");
+            return EXIT_USAGE;
+        }
+    };
+
+    let file = match std::fs::read(input) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return EXIT_USAGE;
+        }
+    };
+    let redacted = match redact(&file, &options) {
+        Ok(redacted) => redacted,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return EXIT_INVALID;
+        }
+    };
+    if let Err(e) = std::fs::write(output, redacted) {
+        eprintln!("{}: {}", output, e);
+        return EXIT_USAGE;
+    }
+    match options.grid {
+        Some(grid) => println!("{}: location coarsened to {}°", output, grid),
+        None => println!("{}: location removed", output),
+    }
+    EXIT_OK
+}
@@ -0,0 +1,68 @@
+use crate::{EXIT_INVALID, EXIT_OK, EXIT_USAGE};
+use cptv_decoder::reader::CptvReader;
+use cptv_encoder::encode_clip;
+use cptv_encoder::resample::{resample_clip, ResampleOptions};
+use std::fs::File;
+use std::io::BufReader;
+
+pub fn run(args: &[String]) -> i32 {
+    let mut fps = None;
+    let mut average = false;
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fps" => match args.next().and_then(|fps| fps.parse().ok()) {
+                Some(value) if value > 0 => fps = Some(value),
+                _ => {
+                    eprintln!("--fps expects a frame rate from 1 to 255");
+                    return EXIT_USAGE;
+                }
+            },
+            "--average" => average = true,
+            _ if arg.starts_with("--") => {
+                eprintln!("Unknown option {}", arg);
+                return EXIT_USAGE;
+            }
+            _ => files.push(arg),
+        }
+    }
+    let fps = match fps {
+        Some(fps) => fps,
+        None => {
+            eprintln!("Expected a target frame rate, e.g. --fps 3");
+            return EXIT_USAGE;
+        }
+    };
+    let (input, output) = match files.as_slice() {
+        [input, output] => (input, output),
+        _ => {
+            eprintln!("Expected an input CPTV file and an output file");
+            return EXIT_USAGE;
+        }
+    };
+
+    let file = match File::open(input) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return EXIT_USAGE;
+        }
+    };
+    let result = CptvReader::new(BufReader::new(file))
+        .and_then(|reader| reader.into_clip())
+        .and_then(|clip| resample_clip(clip, ResampleOptions { fps, average }));
+    let clip = match result {
+        Ok(clip) => clip,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return EXIT_INVALID;
+        }
+    };
+    if let Err(e) = std::fs::write(output, encode_clip(&clip)) {
+        eprintln!("{}: {}", output, e);
+        return EXIT_USAGE;
+    }
+    println!("{}: {} frames at {}fps", output, clip.frames.len(), fps);
+    EXIT_OK
+}
@@ -0,0 +1,142 @@
+use crate::{EXIT_INVALID, EXIT_OK, EXIT_USAGE};
+use cptv_decoder::reader::CptvReader;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const COLUMNS: &str =
+    "file,time_on,frame_temp_c,last_ffc_temp_c,last_ffc_time,bit_width,frame_size,min,max,mean";
+
+pub fn run(args: &[String]) -> i32 {
+    let mut output = None;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => match args.next() {
+                Some(path) => output = Some(path),
+                None => {
+                    eprintln!("--output expects a file name");
+                    return EXIT_USAGE;
+                }
+            },
+            _ if arg.starts_with("--") => {
+                eprintln!("Unknown option {}", arg);
+                return EXIT_USAGE;
+            }
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        eprintln!("No files or directories given");
+        return EXIT_USAGE;
+    }
+
+    let mut files = Vec::new();
+    for path in paths {
+        if let Err(e) = find_cptv_files(Path::new(path), &mut files) {
+            eprintln!("{}: {}", path, e);
+            return EXIT_USAGE;
+        }
+    }
+    let mut writer: Box<dyn Write> = match output {
+        Some(output) => match File::create(output) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(e) => {
+                eprintln!("{}: {}", output, e);
+                return EXIT_USAGE;
+            }
+        },
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+
+    let mut code = EXIT_OK;
+    let result = writeln!(writer, "{}", COLUMNS).and_then(|_| {
+        for file in &files {
+            // A bad file shouldn't stop the rest being exported.
+            if let Err(e) = write_rows(&mut writer, file) {
+                if e.kind() == io::ErrorKind::BrokenPipe {
+                    return Err(e);
+                }
+                eprintln!("{}: {}", file.display(), e);
+                code = EXIT_INVALID;
+            }
+        }
+        writer.flush()
+    });
+    match result {
+        Ok(()) => code,
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => code,
+        Err(e) => {
+            eprintln!("{}", e);
+            EXIT_USAGE
+        }
+    }
+}
+
+// One row per frame of the clip, not counting any background frame.  Frames are decoded one
+// at a time as they are written out.
+fn write_rows<W: Write>(writer: &mut W, path: &Path) -> io::Result<()> {
+    let mut reader = CptvReader::new(BufReader::new(File::open(path)?))?;
+    let name = csv_field(&path.display().to_string());
+    while let Some(frame) = reader.next_frame()? {
+        let pixels = frame.image_data.data();
+        let (mut min, mut max, mut total) = (u16::MAX, u16::MIN, 0u64);
+        for value in pixels {
+            min = min.min(*value);
+            max = max.max(*value);
+            total += *value as u64;
+        }
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{},{},{:.2}",
+            name,
+            frame.time_on,
+            optional(frame.frame_temp_c),
+            optional(frame.last_ffc_temp_c),
+            optional(frame.last_ffc_time),
+            frame.bit_width,
+            frame.frame_size,
+            min,
+            max,
+            total as f64 / pixels.len().max(1) as f64
+        )?;
+    }
+    Ok(())
+}
+
+// Files are taken as given; directories are searched recursively for .cptv files, in name order.
+fn find_cptv_files(path: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries = std::fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            find_cptv_files(&entry, files)?;
+        } else if entry
+            .extension()
+            .is_some_and(|extension| extension == "cptv")
+        {
+            files.push(entry);
+        }
+    }
+    Ok(())
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
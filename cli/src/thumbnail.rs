@@ -0,0 +1,86 @@
+use crate::{EXIT_INVALID, EXIT_OK, EXIT_USAGE};
+use cptv_decoder::reader::CptvReader;
+use cptv_decoder::thumbnail::{thumbnail, FrameSelection, ThumbnailOptions};
+use cptv_shared::v2::colour_map::ColourMap;
+use std::fs::File;
+use std::io::BufReader;
+
+pub fn run(args: &[String]) -> i32 {
+    let mut options = ThumbnailOptions::default();
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--peak" => options.selection = FrameSelection::PeakTemperature,
+            "--crop" => match args.next().and_then(|size| parse_size(size)) {
+                Some(size) => options.crop = Some(size),
+                None => {
+                    eprintln!("--crop expects a size like 64x48");
+                    return EXIT_USAGE;
+                }
+            },
+            "--colour-map" => match args.next().and_then(|name| ColourMap::from_name(name)) {
+                Some(colour_map) => options.colour_map = colour_map,
+                None => {
+                    eprintln!("--colour-map expects one of greyscale, ironbow, viridis");
+                    return EXIT_USAGE;
+                }
+            },
+            "--scale" => match args.next().and_then(|scale| scale.parse().ok()) {
+                Some(scale) if scale > 0 => options.scale = scale,
+                _ => {
+                    eprintln!("--scale expects a positive integer");
+                    return EXIT_USAGE;
+                }
+            },
+            _ if arg.starts_with("--") => {
+                eprintln!("Unknown option {}", arg);
+                return EXIT_USAGE;
+            }
+            _ => files.push(arg),
+        }
+    }
+    let (input, output) = match files.as_slice() {
+        [input, output] => (input, output),
+        _ => {
+            eprintln!("Expected an input CPTV file and an output PNG file");
+            return EXIT_USAGE;
+        }
+    };
+
+    let file = match File::open(input) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return EXIT_USAGE;
+        }
+    };
+    let result = CptvReader::new(BufReader::new(file))
+        .and_then(|mut reader| thumbnail(&mut reader, &options));
+    let thumbnail = match result {
+        Ok(thumbnail) => thumbnail,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return EXIT_INVALID;
+        }
+    };
+    if let Err(e) = std::fs::write(output, &thumbnail.png) {
+        eprintln!("{}: {}", output, e);
+        return EXIT_USAGE;
+    }
+    println!(
+        "{}: frame #{} ({}x{} at {},{})",
+        output,
+        thumbnail.frame_index,
+        thumbnail.bounds.width,
+        thumbnail.bounds.height,
+        thumbnail.bounds.x,
+        thumbnail.bounds.y
+    );
+    EXIT_OK
+}
+
+fn parse_size(size: &str) -> Option<(usize, usize)> {
+    let (width, height) = size.split_once('x')?;
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
@@ -0,0 +1,145 @@
+use crate::{EXIT_INVALID, EXIT_OK, EXIT_USAGE};
+use cptv_decoder::reader::CptvReader;
+use cptv_shared::v2::json::{ClipJson, PixelFormat};
+use serde::Serialize;
+use serde_json::ser::{Formatter, PrettyFormatter};
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Write};
+
+pub fn run(args: &[String]) -> i32 {
+    let mut format = PixelFormat::Rows;
+    let mut files = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--base64" => format = PixelFormat::Base64,
+            _ if arg.starts_with("--") => {
+                eprintln!("Unknown option {}", arg);
+                return EXIT_USAGE;
+            }
+            _ => files.push(arg),
+        }
+    }
+    let (input, output) = match files.as_slice() {
+        [input] => (input, None),
+        [input, output] => (input, Some(output)),
+        _ => {
+            eprintln!("Expected a CPTV file, and optionally an output JSON file");
+            return EXIT_USAGE;
+        }
+    };
+
+    let file = match File::open(input) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return EXIT_USAGE;
+        }
+    };
+    let clip = match CptvReader::new(BufReader::new(file)).and_then(|reader| reader.into_clip()) {
+        Ok(clip) => clip,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return EXIT_INVALID;
+        }
+    };
+    let mut json = Vec::new();
+    let mut serializer =
+        serde_json::Serializer::with_formatter(&mut json, RowsFormatter::default());
+    ClipJson::new(&clip, format)
+        .serialize(&mut serializer)
+        .unwrap();
+    let json = String::from_utf8(json).unwrap();
+    match output {
+        Some(output) => {
+            if let Err(e) = std::fs::write(output, json + "\n") {
+                eprintln!("{}: {}", output, e);
+                return EXIT_USAGE;
+            }
+        }
+        None => println!("{}", json),
+    }
+    EXIT_OK
+}
+
+// Pretty prints JSON, except that arrays inside arrays (the rows of pixels) go on one line each,
+// which keeps them readable and makes diffs line up with rows of the frame.
+#[derive(Default)]
+struct RowsFormatter {
+    pretty: PrettyFormatter<'static>,
+    // Whether each enclosing container is an array, innermost last.
+    in_array: Vec<bool>,
+}
+
+impl RowsFormatter {
+    fn is_compact(&self) -> bool {
+        self.in_array.len() >= 2 && self.in_array[self.in_array.len() - 2..] == [true, true]
+    }
+}
+
+impl Formatter for RowsFormatter {
+    fn begin_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.in_array.push(true);
+        if self.is_compact() {
+            writer.write_all(b"[")
+        } else {
+            self.pretty.begin_array(writer)
+        }
+    }
+
+    fn end_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        let compact = self.is_compact();
+        self.in_array.pop();
+        if compact {
+            writer.write_all(b"]")
+        } else {
+            self.pretty.end_array(writer)
+        }
+    }
+
+    fn begin_array_value<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        if self.is_compact() {
+            writer.write_all(if first { b"" } else { b", " })
+        } else {
+            self.pretty.begin_array_value(writer, first)
+        }
+    }
+
+    fn end_array_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        if self.is_compact() {
+            Ok(())
+        } else {
+            self.pretty.end_array_value(writer)
+        }
+    }
+
+    fn begin_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.in_array.push(false);
+        self.pretty.begin_object(writer)
+    }
+
+    fn end_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.in_array.pop();
+        self.pretty.end_object(writer)
+    }
+
+    fn begin_object_key<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        self.pretty.begin_object_key(writer, first)
+    }
+
+    fn begin_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.pretty.begin_object_value(writer)
+    }
+
+    fn end_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.pretty.end_object_value(writer)
+    }
+}
@@ -0,0 +1,36 @@
+use crate::{EXIT_INVALID, EXIT_OK, EXIT_USAGE};
+use cptv_decoder::reader::CptvReader;
+use cptv_encoder::numpy::write_npz;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+
+pub fn run(args: &[String]) -> i32 {
+    let (input, output) = match args {
+        [input, output] if !input.starts_with("--") && !output.starts_with("--") => (input, output),
+        _ => {
+            eprintln!("Expected an input CPTV file and an output .npz file");
+            return EXIT_USAGE;
+        }
+    };
+
+    let file = match File::open(input) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return EXIT_USAGE;
+        }
+    };
+    let clip = match CptvReader::new(BufReader::new(file)).and_then(|reader| reader.into_clip()) {
+        Ok(clip) => clip,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return EXIT_INVALID;
+        }
+    };
+    if let Err(e) = File::create(output).and_then(|file| write_npz(&clip, BufWriter::new(file))) {
+        eprintln!("{}: {}", output, e);
+        return EXIT_USAGE;
+    }
+    println!("{}: {} frames", output, clip.frames.len());
+    EXIT_OK
+}
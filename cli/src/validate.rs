@@ -0,0 +1,79 @@
+use crate::{EXIT_INVALID, EXIT_OK, EXIT_USAGE};
+use cptv_decoder::validate::{validate, Severity, ValidationReport};
+use serde_json::json;
+
+pub fn run(args: &[String]) -> i32 {
+    let mut strict = false;
+    let mut as_json = false;
+    let mut files = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--strict" => strict = true,
+            "--json" => as_json = true,
+            _ if arg.starts_with("--") => {
+                eprintln!("Unknown option {}", arg);
+                return EXIT_USAGE;
+            }
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        eprintln!("No files given to validate");
+        return EXIT_USAGE;
+    }
+
+    let mut code = EXIT_OK;
+    let mut results = Vec::new();
+    for file in files {
+        let bytes = match std::fs::read(file) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                code = EXIT_USAGE;
+                continue;
+            }
+        };
+        let report = validate(&bytes);
+        let passed = report.is_valid() && !(strict && report.has_warnings());
+        if !passed && code == EXIT_OK {
+            code = EXIT_INVALID;
+        }
+        if as_json {
+            results.push(json!({ "file": file, "valid": passed, "report": report }));
+        } else {
+            print_report(file, passed, &report);
+        }
+    }
+    if as_json {
+        println!("{}", serde_json::to_string_pretty(&results).unwrap());
+    }
+    code
+}
+
+fn print_report(file: &str, passed: bool, report: &ValidationReport) {
+    println!(
+        "{}: {} ({} frames{})",
+        file,
+        if passed { "OK" } else { "INVALID" },
+        report.frame_count,
+        if report.has_background_frame {
+            " + background"
+        } else {
+            ""
+        }
+    );
+    for issue in &report.issues {
+        let severity = match issue.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let mut location = String::new();
+        if let Some(frame_index) = issue.frame_index {
+            location.push_str(&format!(" frame #{}", frame_index));
+        }
+        if let Some(offset) = issue.offset {
+            location.push_str(&format!(" @{}", offset));
+        }
+        println!("  {}[{:?}]{}: {}", severity, issue.kind, location, issue.message);
+    }
+}
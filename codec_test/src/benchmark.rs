@@ -0,0 +1,625 @@
+// Runs clips through a range of compression strategies, so that format decisions can be made
+// with numbers.  Each strategy is a predictor, a way of packing the residuals it leaves, and a
+// general purpose compressor on top.  Only the pixel data is encoded; frame headers are the same
+// whatever the strategy.
+//
+// The baseline is the real thing: frames written by the encoder's `push_frame`, frame headers
+// and all, and read back by `CptvReader`.
+//
+// A strategy only counts for a clip if decoding what it wrote gives back every pixel exactly.
+
+use cptv_decoder::reader::CptvReader;
+use cptv_encoder::{push_frame_with_predictor, push_header};
+use cptv_shared::v2::types::{Cptv2Header, CptvFrame, SpatialPredictor};
+use cptv_shared::CptvHeader;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::json;
+use std::io;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
+
+const ZSTD_LEVELS: [i32; 4] = [1, 3, 9, 19];
+
+/// How each pixel is predicted within a frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Spatial {
+    None,
+    /// Difference from the previous pixel, in a snaking scan that reverses direction on every
+    /// other row.  This is what CPTV v2 does.
+    Snake,
+    /// The median edge detector from LOCO-I, predicting from the pixels to the left, above, and
+    /// above left.
+    Med,
+}
+
+/// What is predicted: the pixels themselves, or (with `temporal`) their difference from the
+/// previous frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Predictor {
+    pub temporal: bool,
+    pub spatial: Spatial,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Packing {
+    /// 8 or 16 bits per residual, whichever the frame needs, as CPTV v2 does.
+    Bytes,
+    /// The fewest bits per residual the frame needs, from 1 to 32.
+    Minimal,
+    /// A CPTV file, from the encoder.  Only the temporal snake and MED predictors, and gzip, are
+    /// available, as those are all the format has.
+    Cptv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compressor {
+    Gzip(u32),
+    Zstd(i32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Strategy {
+    pub predictor: Predictor,
+    pub packing: Packing,
+    pub compressor: Compressor,
+}
+
+impl Strategy {
+    pub fn name(&self) -> String {
+        let predictor = match (self.predictor.temporal, self.predictor.spatial) {
+            (false, Spatial::None) => "none",
+            (false, Spatial::Snake) => "snake",
+            (false, Spatial::Med) => "med",
+            (true, Spatial::None) => "temporal",
+            (true, Spatial::Snake) => "temporal+snake",
+            (true, Spatial::Med) => "temporal+med",
+        };
+        let packing = match self.packing {
+            Packing::Bytes => "8/16",
+            Packing::Minimal => "minimal",
+            Packing::Cptv => "cptv",
+        };
+        let compressor = match self.compressor {
+            Compressor::Gzip(level) => format!("gzip-{}", level),
+            Compressor::Zstd(level) => format!("zstd-{}", level),
+        };
+        format!("{} {} {}", predictor, packing, compressor)
+    }
+}
+
+/// The encoder as it is, with each predictor it has, then the current format (temporal+snake,
+/// 8/16 bit packing) with every gzip level and a range of zstd levels, then each other predictor
+/// and packing with gzip at its default level.
+pub fn strategies() -> Vec<Strategy> {
+    let current = Predictor {
+        temporal: true,
+        spatial: Spatial::Snake,
+    };
+    let mut strategies: Vec<Strategy> = [Spatial::Snake, Spatial::Med]
+        .into_iter()
+        .map(|spatial| Strategy {
+            predictor: Predictor {
+                temporal: true,
+                spatial,
+            },
+            packing: Packing::Cptv,
+            compressor: Compressor::Gzip(6),
+        })
+        .collect();
+    strategies.extend(
+        (1..=9)
+            .map(Compressor::Gzip)
+            .chain(ZSTD_LEVELS.iter().copied().map(Compressor::Zstd))
+            .map(|compressor| Strategy {
+                predictor: current,
+                packing: Packing::Bytes,
+                compressor,
+            }),
+    );
+    for temporal in [true, false] {
+        for spatial in [Spatial::Snake, Spatial::Med, Spatial::None] {
+            for packing in [Packing::Bytes, Packing::Minimal] {
+                let predictor = Predictor { temporal, spatial };
+                if predictor != current || packing != Packing::Bytes {
+                    strategies.push(Strategy {
+                        predictor,
+                        packing,
+                        compressor: Compressor::Gzip(6),
+                    });
+                }
+            }
+        }
+    }
+    strategies
+}
+
+/// How one strategy did on one clip.
+pub struct Run {
+    pub compressed_size: usize,
+    pub encode: Duration,
+    pub decode: Duration,
+    /// Why the round trip failed, if it did.
+    pub error: Option<String>,
+}
+
+/// Runs `strategy` on the frames of a clip.  Only the baseline writes `header`.
+pub fn run(strategy: &Strategy, header: &Cptv2Header, frames: &[CptvFrame]) -> Run {
+    let start = Instant::now();
+    let encoded = match strategy.packing {
+        Packing::Cptv => encode_cptv(strategy, header, frames),
+        _ => encode(strategy, frames),
+    };
+    let encode_time = start.elapsed();
+    let start = Instant::now();
+    let decoded = encoded
+        .as_ref()
+        .map_err(|e| e.to_string())
+        .and_then(|encoded| {
+            match strategy.packing {
+                Packing::Cptv => decode_cptv(encoded),
+                _ => decode(strategy, encoded, frames),
+            }
+            .map_err(|e| e.to_string())
+        });
+    let decode_time = start.elapsed();
+    Run {
+        compressed_size: encoded.map(|encoded| encoded.len()).unwrap_or(0),
+        encode: encode_time,
+        decode: decode_time,
+        error: decoded
+            .and_then(|decoded| check_pixels(frames, &decoded))
+            .err(),
+    }
+}
+
+fn encode(strategy: &Strategy, frames: &[CptvFrame]) -> io::Result<Vec<u8>> {
+    let mut packed = Vec::new();
+    let mut residuals = Vec::new();
+    let mut prev: Option<&[u16]> = None;
+    for frame in frames {
+        let pixels = frame.image_data.data();
+        predict(
+            strategy.predictor,
+            prev,
+            pixels,
+            frame.image_data.width(),
+            &mut residuals,
+        );
+        pack(strategy.packing, &residuals, &mut packed)?;
+        prev = Some(pixels);
+    }
+    compress(strategy.compressor, &packed)
+}
+
+fn compress(compressor: Compressor, data: &[u8]) -> io::Result<Vec<u8>> {
+    match compressor {
+        Compressor::Gzip(level) => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level));
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        Compressor::Zstd(level) => zstd::bulk::compress(data, level),
+    }
+}
+
+// Writes a CPTV file the way the encoder does, predicting every frame from the one before it.
+fn encode_cptv(
+    strategy: &Strategy,
+    header: &Cptv2Header,
+    frames: &[CptvFrame],
+) -> io::Result<Vec<u8>> {
+    let predictor = match (
+        strategy.predictor.temporal,
+        strategy.predictor.spatial,
+        strategy.compressor,
+    ) {
+        (true, Spatial::Snake, Compressor::Gzip(_)) => SpatialPredictor::Snake,
+        (true, Spatial::Med, Compressor::Gzip(_)) => SpatialPredictor::Med,
+        _ => {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("CPTV can't be written as {}", strategy.name()),
+            ))
+        }
+    };
+    if frames
+        .iter()
+        .any(|frame| frame.image_data.data().is_empty())
+    {
+        return Err(Error::new(ErrorKind::InvalidData, "Frame has no pixels"));
+    }
+    let mut output = Vec::new();
+    push_header(&mut output, &CptvHeader::V2(header.clone()));
+    let mut scratch = vec![0; frames[0].image_data.data().len()];
+    let mut bit_widths = [0, 0];
+    let mut prev = None;
+    for frame in frames {
+        scratch.resize(frame.image_data.data().len(), 0);
+        push_frame_with_predictor(
+            &mut output,
+            frame,
+            prev,
+            predictor,
+            &mut bit_widths,
+            &mut scratch,
+        );
+        prev = Some(frame);
+    }
+    compress(strategy.compressor, &output)
+}
+
+// Any background frame is put back at the start, where it was in `frames`.
+fn decode_cptv(encoded: &[u8]) -> io::Result<Vec<Vec<u16>>> {
+    let mut reader = CptvReader::new(encoded)?;
+    let mut frames = reader
+        .background()
+        .map(|background| background.image_data.data().to_vec())
+        .into_iter()
+        .collect::<Vec<_>>();
+    for frame in &mut reader {
+        frames.push(frame?.image_data.data().to_vec());
+    }
+    Ok(frames)
+}
+
+// Decodes frames with the same dimensions as `like`.
+fn decode(strategy: &Strategy, encoded: &[u8], like: &[CptvFrame]) -> io::Result<Vec<Vec<u16>>> {
+    let packed = match strategy.compressor {
+        Compressor::Gzip(_) => {
+            let mut packed = Vec::new();
+            GzDecoder::new(encoded).read_to_end(&mut packed)?;
+            packed
+        }
+        Compressor::Zstd(_) => zstd::stream::decode_all(encoded)?,
+    };
+    let mut input = &packed[..];
+    let mut residuals = Vec::new();
+    let mut frames: Vec<Vec<u16>> = Vec::with_capacity(like.len());
+    for frame in like {
+        let count = frame.image_data.width() * frame.image_data.height();
+        input = unpack(input, count, &mut residuals)?;
+        let pixels = unpredict(
+            strategy.predictor,
+            frames.last().map(|prev| &prev[..]),
+            &residuals,
+            frame.image_data.width(),
+        );
+        frames.push(pixels);
+    }
+    Ok(frames)
+}
+
+fn check_pixels(original: &[CptvFrame], decoded: &[Vec<u16>]) -> Result<(), String> {
+    for (index, (original, decoded)) in original.iter().zip(decoded).enumerate() {
+        let width = original.image_data.width();
+        if let Some(i) = original
+            .image_data
+            .data()
+            .iter()
+            .zip(decoded)
+            .position(|(a, b)| a != b)
+        {
+            return Err(format!(
+                "Frame #{} differs at {},{}: {} became {}",
+                index,
+                i % width,
+                i / width,
+                original.image_data.data()[i],
+                decoded[i]
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Order in which a snake scan visits the pixels of a `width` wide frame.
+fn snake_order(width: usize, count: usize) -> impl Iterator<Item = usize> {
+    (0..width)
+        .chain((0..width).rev())
+        .cycle()
+        .take(count)
+        .enumerate()
+        .map(move |(index, x)| (index / width) * width + x)
+}
+
+fn med(left: i32, above: i32, above_left: i32) -> i32 {
+    if above_left >= left.max(above) {
+        left.min(above)
+    } else if above_left <= left.min(above) {
+        left.max(above)
+    } else {
+        left + above - above_left
+    }
+}
+
+// Prediction for the pixel at `index` from values already seen, in row order.  The first row
+// predicts from the left, and the first column from above.
+fn med_prediction(values: &[i32], index: usize, width: usize) -> i32 {
+    let (x, y) = (index % width, index / width);
+    match (x, y) {
+        (0, 0) => 0,
+        (_, 0) => values[index - 1],
+        (0, _) => values[index - width],
+        _ => med(
+            values[index - 1],
+            values[index - width],
+            values[index - width - 1],
+        ),
+    }
+}
+
+fn predict(
+    predictor: Predictor,
+    prev: Option<&[u16]>,
+    pixels: &[u16],
+    width: usize,
+    residuals: &mut Vec<i32>,
+) {
+    let values: Vec<i32> = match prev.filter(|_| predictor.temporal) {
+        Some(prev) => pixels
+            .iter()
+            .zip(prev)
+            .map(|(pixel, prev)| *pixel as i32 - *prev as i32)
+            .collect(),
+        None => pixels.iter().map(|pixel| *pixel as i32).collect(),
+    };
+    residuals.clear();
+    match predictor.spatial {
+        Spatial::None => residuals.extend_from_slice(&values),
+        Spatial::Snake => {
+            let mut last = 0;
+            for index in snake_order(width, values.len()) {
+                residuals.push(values[index] - last);
+                last = values[index];
+            }
+        }
+        Spatial::Med => residuals.extend(
+            (0..values.len()).map(|index| values[index] - med_prediction(&values, index, width)),
+        ),
+    }
+}
+
+fn unpredict(
+    predictor: Predictor,
+    prev: Option<&[u16]>,
+    residuals: &[i32],
+    width: usize,
+) -> Vec<u16> {
+    let mut values = vec![0i32; residuals.len()];
+    match predictor.spatial {
+        Spatial::None => values.copy_from_slice(residuals),
+        Spatial::Snake => {
+            let mut last = 0;
+            for (residual, index) in residuals.iter().zip(snake_order(width, residuals.len())) {
+                last += residual;
+                values[index] = last;
+            }
+        }
+        Spatial::Med => {
+            for index in 0..values.len() {
+                values[index] = residuals[index] + med_prediction(&values, index, width);
+            }
+        }
+    }
+    match prev.filter(|_| predictor.temporal) {
+        Some(prev) => values
+            .iter()
+            .zip(prev)
+            .map(|(value, prev)| (value + *prev as i32) as u16)
+            .collect(),
+        None => values.iter().map(|value| *value as u16).collect(),
+    }
+}
+
+// Signed bits needed to hold `value`.
+fn bits_needed(value: i32) -> u8 {
+    let magnitude = if value < 0 { !value } else { value };
+    (33 - magnitude.leading_zeros()) as u8
+}
+
+// Each frame is its bit width as a byte, then the first residual as a little endian i32, then the
+// rest packed MSB first.  Like CPTV, the first residual doesn't count towards the bit width.
+fn pack(packing: Packing, residuals: &[i32], output: &mut Vec<u8>) -> io::Result<()> {
+    let (first, rest) = residuals
+        .split_first()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Frame has no pixels"))?;
+    let width = match packing {
+        Packing::Bytes | Packing::Cptv => {
+            // The same choice as the encoder makes, which assumes 16 bits is always enough.
+            let max = rest.iter().map(|value| value.abs()).max().unwrap_or(0);
+            if 33 - max.leading_zeros() >= 8 {
+                16
+            } else {
+                8
+            }
+        }
+        Packing::Minimal => rest
+            .iter()
+            .map(|value| bits_needed(*value))
+            .max()
+            .unwrap_or(1),
+    };
+    output.push(width);
+    output.extend_from_slice(&first.to_le_bytes());
+    match width {
+        8 => output.extend(rest.iter().map(|value| *value as u8)),
+        16 => {
+            for value in rest {
+                output.extend_from_slice(&(*value as u16).to_be_bytes());
+            }
+        }
+        _ => {
+            let mask = (1u64 << width) - 1;
+            let mut scratch = 0u64;
+            let mut bits = 0;
+            for value in rest {
+                scratch = (scratch << width) | (*value as u32 as u64 & mask);
+                bits += width as u32;
+                while bits >= 8 {
+                    bits -= 8;
+                    output.push((scratch >> bits) as u8);
+                }
+            }
+            if bits > 0 {
+                output.push((scratch << (8 - bits)) as u8);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn unpack<'a>(input: &'a [u8], count: usize, residuals: &mut Vec<i32>) -> io::Result<&'a [u8]> {
+    let truncated = || Error::new(ErrorKind::UnexpectedEof, "Packed frame is cut off");
+    if count == 0 {
+        return Err(Error::new(ErrorKind::InvalidData, "Frame has no pixels"));
+    }
+    if input.len() < 5 {
+        return Err(truncated());
+    }
+    let width = input[0] as u32;
+    if !(1..=32).contains(&width) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Bad bit width {}", width),
+        ));
+    }
+    residuals.clear();
+    residuals.push(i32::from_le_bytes([input[1], input[2], input[3], input[4]]));
+    let length = ((count - 1) * width as usize).div_ceil(8);
+    let packed = input.get(5..5 + length).ok_or_else(truncated)?;
+    let mut scratch = 0u64;
+    let mut bits = 0;
+    let mut bytes = packed.iter();
+    for _ in 1..count {
+        while bits < width {
+            scratch = (scratch << 8) | *bytes.next().unwrap() as u64;
+            bits += 8;
+        }
+        bits -= width;
+        let value = (scratch >> bits) as u32 & ((1u64 << width) - 1) as u32;
+        // Sign extend from `width` bits.
+        let shift = 32 - width;
+        residuals.push(((value << shift) as i32) >> shift);
+    }
+    Ok(&input[5 + length..])
+}
+
+/// Totals for one strategy across the corpus.
+pub struct StrategyTotals {
+    pub strategy: Strategy,
+    pub frames: usize,
+    pub raw_size: usize,
+    pub compressed_size: usize,
+    pub encode: Duration,
+    pub decode: Duration,
+    /// Clips the strategy didn't round trip, and why.
+    pub failures: Vec<(String, String)>,
+}
+
+impl StrategyTotals {
+    pub fn new(strategy: Strategy) -> StrategyTotals {
+        StrategyTotals {
+            strategy,
+            frames: 0,
+            raw_size: 0,
+            compressed_size: 0,
+            encode: Duration::ZERO,
+            decode: Duration::ZERO,
+            failures: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, clip: &str, frames: usize, raw_size: usize, run: &Run) {
+        match &run.error {
+            Some(error) => self.failures.push((clip.to_string(), error.clone())),
+            None => {
+                self.frames += frames;
+                self.raw_size += raw_size;
+                self.compressed_size += run.compressed_size;
+                self.encode += run.encode;
+                self.decode += run.decode;
+            }
+        }
+    }
+
+    fn ratio(&self) -> f64 {
+        self.raw_size as f64 / self.compressed_size.max(1) as f64
+    }
+
+    fn fps(&self, duration: Duration) -> f64 {
+        self.frames as f64 / duration.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut result = json!({
+            "strategy": self.strategy.name(),
+            "failed": self.failures.len(),
+        });
+        // Numbers only count if every clip round tripped.
+        if self.failures.is_empty() {
+            result["frames"] = json!(self.frames);
+            result["raw_size"] = json!(self.raw_size);
+            result["compressed_size"] = json!(self.compressed_size);
+            result["ratio"] = json!(self.ratio());
+            result["encode_fps"] = json!(self.fps(self.encode));
+            result["decode_fps"] = json!(self.fps(self.decode));
+        } else {
+            result["failures"] = self
+                .failures
+                .iter()
+                .map(|(clip, error)| json!({ "path": clip, "error": error }))
+                .collect();
+        }
+        result
+    }
+
+    pub fn csv_row(&self) -> String {
+        if self.failures.is_empty() {
+            format!(
+                "{},0,{},{},{},{:.3},{:.1},{:.1}",
+                self.strategy.name(),
+                self.frames,
+                self.raw_size,
+                self.compressed_size,
+                self.ratio(),
+                self.fps(self.encode),
+                self.fps(self.decode)
+            )
+        } else {
+            format!("{},{},,,,,,", self.strategy.name(), self.failures.len())
+        }
+    }
+}
+
+pub const CSV_COLUMNS: &str =
+    "strategy,failed,frames,raw_size,compressed_size,ratio,encode_fps,decode_fps";
+
+pub fn print_totals(totals: &[StrategyTotals]) {
+    println!(
+        "\n{:<32} {:>12} {:>8} {:>12} {:>12}",
+        "Strategy", "Size", "Ratio", "Encode fps", "Decode fps"
+    );
+    for total in totals {
+        if total.failures.is_empty() {
+            println!(
+                "{:<32} {:>12} {:>7.2}x {:>12.1} {:>12.1}",
+                total.strategy.name(),
+                total.compressed_size,
+                total.ratio(),
+                total.fps(total.encode),
+                total.fps(total.decode)
+            );
+        } else {
+            let (clip, error) = &total.failures[0];
+            println!(
+                "{:<32} round trip failed on {} clips, e.g. {}: {}",
+                total.strategy.name(),
+                total.failures.len(),
+                clip,
+                error
+            );
+        }
+    }
+}
@@ -1,77 +1,444 @@
-use std::borrow::Borrow;
 use std::fs::File;
 use std::io::{Error, ErrorKind, Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
-use nom::bytes::streaming::{tag, take};
-use nom::number::streaming::le_u8;
-use cptv_decoder::decoder;
+use cptv_decoder::reader::CptvReader;
+use cptv_decoder::verify::verify_roundtrip;
 use cptv_encoder::{push_frame, push_header};
 use flate2::write::GzEncoder;
-use flate2::read::GzDecoder;
-use flate2::{Compression, Decompress, FlushDecompress};
+use flate2::Compression;
 use std::io;
-use std::io::prelude::*;
 
-use cptv_shared::v2::{decode_cptv2_header, decode_frame_header_v2, unpack_frame_v2};
-
-#[cfg(feature = "cptv3-support")]
-use cptv_shared::v3::decode_cptv3_header;
 
 use cptv_shared::CptvHeader;
 use cptv_shared::CptvHeader::V2;
 use cptv_shared::v2::types::CptvFrame;
 
+use benchmark::StrategyTotals;
+use rayon::prelude::*;
+use serde_json::json;
 use walkdir::WalkDir;
 
+mod benchmark;
+
+const USAGE: &str = "Usage: codec_test [options] [<directories, files or globs>...]
+
+Decodes every CPTV file found, re-encodes it and checks nothing was lost, and summarises failures,
+compression ratios and timings.  Directories are searched recursively for .cptv files; with no arguments, the
+current directory is.
+
+With --benchmark, each file is instead run through a range of compression strategies (gzip and
+zstd levels, 8/16 bit or minimal width packing, and different predictors), reporting the
+compressed size and encode and decode speed of each.  A strategy's numbers only count if it gives
+back every pixel exactly.  Use --threads 1 for the most reliable timings.
+
+Options:
+  --threads <n>      Size of the thread pool (default: one per core)
+  --benchmark        Compare compression strategies
+  --json <file>      Write the summary and per-file (or per-strategy) results as JSON
+  --csv <file>       Write per-file (or per-strategy) results as CSV
+";
+
+struct Options {
+    threads: usize,
+    benchmark: bool,
+    json: Option<String>,
+    csv: Option<String>,
+    inputs: Vec<String>,
+}
+
+/// What we found out about one file of the corpus.
+struct FileResult {
+    path: PathBuf,
+    outcome: Result<FileStats, String>,
+}
+
+struct FileStats {
+    frames: usize,
+    model: Option<String>,
+    truncated_at: Option<usize>,
+    original_size: usize,
+    reencoded_size: usize,
+    // Size of the frames as plain 16 bit pixels.
+    raw_size: usize,
+    decode: Duration,
+    pack: Duration,
+    gz: Duration,
+}
+
 fn main() -> std::io::Result<()> {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) if message.is_empty() => {
+            print!("{}", USAGE);
+            return Ok(());
+        }
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let files = find_files(&options.inputs)?;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads)
+        .build()
+        .map_err(Error::other)?;
+    if options.benchmark {
+        return run_benchmark(&files, &pool, &options);
+    }
 
-    // TODO: Take a folder as args from the CLI
+    let start = Instant::now();
+    let results: Vec<FileResult> = pool.install(|| {
+        files
+            .par_iter()
+            .map(|path| FileResult {
+                path: path.clone(),
+                outcome: check_cptv(path).map_err(|e| e.to_string()),
+            })
+            .collect()
+    });
+    let elapsed = start.elapsed();
 
-    // TODO - par_iter over files.
+    print_summary(&results, pool.current_num_threads(), elapsed);
+    if let Some(path) = &options.json {
+        let output = results_json(&results, pool.current_num_threads(), elapsed);
+        std::fs::write(path, serde_json::to_string_pretty(&output)?)?;
+    }
+    if let Some(path) = &options.csv {
+        write_csv(&results, path)?;
+    }
+    if results.iter().any(|result| result.outcome.is_err()) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
 
-    // Load cptv:
-    // Need a synchronous version of the decoder first?
-    // TODO: Streaming decoder?
-    let path = if Path::new(&"../cptv-files").exists() {
-        "../cptv-files"
-    } else {
-        "./cptv-files"
+/// A file's frame count and raw size, and how each strategy did on it.
+struct BenchmarkedFile {
+    frames: usize,
+    raw_size: usize,
+    runs: Vec<benchmark::Run>,
+}
+
+fn run_benchmark(files: &[PathBuf], pool: &rayon::ThreadPool, options: &Options) -> io::Result<()> {
+    let strategies = benchmark::strategies();
+    let start = Instant::now();
+    let results: Vec<(&PathBuf, Result<BenchmarkedFile, String>)> = pool.install(|| {
+        files
+            .par_iter()
+            .map(|path| {
+                let runs = decode_file(path).map_err(|e| e.to_string()).and_then(|decoded| match &decoded.header {
+                    V2(header) => Ok(BenchmarkedFile {
+                        frames: decoded.frames.len(),
+                        raw_size: decoded.frames.iter().map(|frame| frame.image_data.data().len() * 2).sum(),
+                        runs: strategies.iter().map(|strategy| benchmark::run(strategy, header, &decoded.frames)).collect(),
+                    }),
+                    _ => Err("Only CPTV v2 files can be benchmarked".to_string()),
+                });
+                (path, runs)
+            })
+            .collect()
+    });
+
+    let mut totals: Vec<StrategyTotals> = strategies.iter().copied().map(StrategyTotals::new).collect();
+    let mut failures = Vec::new();
+    for (path, result) in &results {
+        match result {
+            Ok(file) => {
+                for (total, run) in totals.iter_mut().zip(&file.runs) {
+                    total.add(&path.display().to_string(), file.frames, file.raw_size, run);
+                }
+            }
+            Err(error) => failures.push((path, error)),
+        }
+    }
+    println!(
+        "Benchmarked {} strategies on {} files ({} failed to decode) with {} threads in {:?}",
+        strategies.len(),
+        results.len(),
+        failures.len(),
+        pool.current_num_threads(),
+        start.elapsed()
+    );
+    for (path, error) in &failures {
+        println!("  {}: {}", path.display(), error);
+    }
+    benchmark::print_totals(&totals);
+
+    if let Some(path) = &options.json {
+        let output = json!({
+            "files": results.len(),
+            "decode_failures": failures
+                .iter()
+                .map(|(path, error)| json!({ "path": path, "error": error }))
+                .collect::<Vec<_>>(),
+            "strategies": totals.iter().map(|total| total.to_json()).collect::<Vec<_>>(),
+        });
+        std::fs::write(path, serde_json::to_string_pretty(&output)?)?;
+    }
+    if let Some(path) = &options.csv {
+        let mut output = io::BufWriter::new(File::create(path)?);
+        writeln!(output, "{}", benchmark::CSV_COLUMNS)?;
+        for total in &totals {
+            writeln!(output, "{}", total.csv_row())?;
+        }
+        output.flush()?;
+    }
+    Ok(())
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        threads: 0,
+        benchmark: false,
+        json: None,
+        csv: None,
+        inputs: Vec::new(),
     };
-    let src_dir = WalkDir::new("./");
-    let mut max = 0;
-    for entry in src_dir {
-        if max == 10 {
-            break;
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} expects a value", name));
+        match arg.as_str() {
+            "--threads" => {
+                options.threads = value(&arg)?
+                    .parse()
+                    .map_err(|_| "--threads expects a number".to_string())?
+            }
+            "--benchmark" => options.benchmark = true,
+            "--json" => options.json = Some(value(&arg)?),
+            "--csv" => options.csv = Some(value(&arg)?),
+            "--help" | "-h" => return Err(String::new()),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => options.inputs.push(arg),
         }
-        if let Ok(entry) = entry {
-            if let Some(ext) = entry.path().extension() {
-                if ext == "cptv" {
-                    check_cptv(entry.path());
-                    max += 1;
+    }
+    if options.inputs.is_empty() {
+        options.inputs.push("./".to_string());
+    }
+    Ok(options)
+}
+
+// Directories are walked for .cptv files, globs are expanded, and anything else is taken as a
+// file.  Each file is only checked once, in path order.
+fn find_files(inputs: &[String]) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if Path::new(input).is_dir() {
+            for entry in WalkDir::new(input) {
+                let entry = entry.map_err(Error::other)?;
+                if entry.file_type().is_file()
+                    && entry.path().extension().is_some_and(|ext| ext == "cptv")
+                {
+                    files.push(entry.into_path());
+                }
+            }
+        } else if input.contains(['*', '?', '[']) {
+            let paths = glob::glob(input)
+                .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("{}: {}", input, e)))?;
+            for path in paths {
+                let path = path.map_err(Error::other)?;
+                if path.is_file() {
+                    files.push(path);
                 }
             }
+        } else {
+            files.push(PathBuf::from(input));
         }
     }
-    Ok(())
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+fn print_summary(results: &[FileResult], threads: usize, elapsed: Duration) {
+    let stats: Vec<&FileStats> = results.iter().filter_map(|result| result.outcome.as_ref().ok()).collect();
+    let failures: Vec<(&PathBuf, &String)> = results
+        .iter()
+        .filter_map(|result| result.outcome.as_ref().err().map(|e| (&result.path, e)))
+        .collect();
+    println!(
+        "Checked {} files ({} failed, {} frames) with {} threads in {:?}",
+        results.len(),
+        failures.len(),
+        stats.iter().map(|stats| stats.frames).sum::<usize>(),
+        threads,
+        elapsed
+    );
+    if !failures.is_empty() {
+        println!("\nFailures:");
+        for (path, error) in &failures {
+            println!("  {}: {}", path.display(), error);
+        }
+    }
+    let truncated = stats.iter().filter(|stats| stats.truncated_at.is_some()).count();
+    if truncated != 0 {
+        println!("\n{} files were truncated; their complete frames were still checked", truncated);
+    }
+    if stats.is_empty() {
+        return;
+    }
+
+    let (original, reencoded) = overall_ratios(&stats);
+    let mut ratios: Vec<f64> = stats.iter().copied().map(reencoded_ratio).collect();
+    ratios.sort_by(|a, b| a.total_cmp(b));
+    println!("\nCompression (raw 16 bit frames / gzipped size):");
+    println!("  original {:.2}x, re-encoded {:.2}x", original, reencoded);
+    println!(
+        "  re-encoded per file: min {:.2}x, median {:.2}x, max {:.2}x",
+        ratios[0],
+        percentile(&ratios, 50.0),
+        ratios[ratios.len() - 1]
+    );
+
+    println!("\nTimings per file:     p50         p90         p99         max");
+    for (name, durations) in stage_timings(&stats) {
+        println!(
+            "  {:<8} {:>11.3?} {:>11.3?} {:>11.3?} {:>11.3?}",
+            name,
+            percentile(&durations, 50.0),
+            percentile(&durations, 90.0),
+            percentile(&durations, 99.0),
+            durations[durations.len() - 1]
+        );
+    }
+}
+
+fn results_json(results: &[FileResult], threads: usize, elapsed: Duration) -> serde_json::Value {
+    let stats: Vec<&FileStats> = results.iter().filter_map(|result| result.outcome.as_ref().ok()).collect();
+    let mut summary = json!({
+        "files": results.len(),
+        "failed": results.len() - stats.len(),
+        "frames": stats.iter().map(|stats| stats.frames).sum::<usize>(),
+        "threads": threads,
+        "elapsed_ms": millis(elapsed),
+    });
+    if !stats.is_empty() {
+        let (original, reencoded) = overall_ratios(&stats);
+        summary["original_ratio"] = json!(original);
+        summary["reencoded_ratio"] = json!(reencoded);
+        for (name, durations) in stage_timings(&stats) {
+            summary[format!("{}_ms", name)] = json!({
+                "p50": millis(percentile(&durations, 50.0)),
+                "p90": millis(percentile(&durations, 90.0)),
+                "p99": millis(percentile(&durations, 99.0)),
+                "max": millis(durations[durations.len() - 1]),
+            });
+        }
+    }
+    let files: Vec<serde_json::Value> = results
+        .iter()
+        .map(|result| match &result.outcome {
+            Ok(stats) => json!({
+                "path": result.path,
+                "frames": stats.frames,
+                "model": stats.model,
+                "truncated_at": stats.truncated_at,
+                "original_size": stats.original_size,
+                "reencoded_size": stats.reencoded_size,
+                "raw_size": stats.raw_size,
+                "decode_ms": millis(stats.decode),
+                "pack_ms": millis(stats.pack),
+                "gz_ms": millis(stats.gz),
+            }),
+            Err(error) => json!({ "path": result.path, "error": error }),
+        })
+        .collect();
+    json!({ "summary": summary, "files": files })
+}
+
+fn write_csv(results: &[FileResult], path: &str) -> io::Result<()> {
+    let mut output = io::BufWriter::new(File::create(path)?);
+    writeln!(
+        output,
+        "path,error,frames,model,truncated_at,original_size,reencoded_size,raw_size,decode_ms,pack_ms,gz_ms"
+    )?;
+    for result in results {
+        let path = csv_field(&result.path.display().to_string());
+        match &result.outcome {
+            Ok(stats) => writeln!(
+                output,
+                "{},,{},{},{},{},{},{},{:.3},{:.3},{:.3}",
+                path,
+                stats.frames,
+                csv_field(stats.model.as_deref().unwrap_or("")),
+                stats.truncated_at.map(|offset| offset.to_string()).unwrap_or_default(),
+                stats.original_size,
+                stats.reencoded_size,
+                stats.raw_size,
+                millis(stats.decode),
+                millis(stats.pack),
+                millis(stats.gz)
+            )?,
+            Err(error) => writeln!(output, "{},{},,,,,,,,,", path, csv_field(error))?,
+        }
+    }
+    output.flush()
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
 }
 
+// Raw size over gzipped size, across the whole corpus, for the original files and for our
+// re-encoding of them.
+fn overall_ratios(stats: &[&FileStats]) -> (f64, f64) {
+    let raw: usize = stats.iter().map(|stats| stats.raw_size).sum();
+    let original: usize = stats.iter().map(|stats| stats.original_size).sum();
+    let reencoded: usize = stats.iter().map(|stats| stats.reencoded_size).sum();
+    (raw as f64 / original.max(1) as f64, raw as f64 / reencoded.max(1) as f64)
+}
+
+fn reencoded_ratio(stats: &FileStats) -> f64 {
+    stats.raw_size as f64 / stats.reencoded_size.max(1) as f64
+}
+
+// Sorted durations of each stage, across all the files that decoded.
+fn stage_timings(stats: &[&FileStats]) -> Vec<(&'static str, Vec<Duration>)> {
+    let sorted = |stage: fn(&FileStats) -> Duration| {
+        let mut durations: Vec<Duration> = stats.iter().map(|stats| stage(stats)).collect();
+        durations.sort();
+        durations
+    };
+    vec![
+        ("decode", sorted(|stats| stats.decode)),
+        ("pack", sorted(|stats| stats.pack)),
+        ("gz", sorted(|stats| stats.gz)),
+    ]
+}
 
-fn check_cptv(path: &Path) -> std::io::Result<()> {
-    let (original_size, (header, frames)) = decode_file(path)?;
+// Nearest-rank percentile of sorted, non-empty values.
+fn percentile<T: Copy>(sorted: &[T], percent: f64) -> T {
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn check_cptv(path: &Path) -> std::io::Result<FileStats> {
+    let start = Instant::now();
+    let DecodedFile { bytes: original_bytes, header, frames, truncated_at } = decode_file(path)?;
+    let decode = start.elapsed();
     if let V2(ref v2_header) = header {
+        if frames.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "No frames"));
+        }
         let start = Instant::now();
         // Compress cptv:
         let mut output = Vec::with_capacity(v2_header.width as usize * v2_header.height as usize * 16 * frames.len());
         push_header(&mut output, &header);
-        debug_assert_ne!(frames.len(), 0);
         // Add the first frame
 
         let mut scratch = vec![0; v2_header.width as usize * v2_header.height as usize];
         let mut bit_widths = [0, 0];
         push_frame(&mut output, &frames[0], None, &mut bit_widths, &mut scratch);
         // TODO: When array_windows is stabilised, use that instead.
-        for (frame_num, window) in frames.windows(2).enumerate() {
+        for window in frames.windows(2) {
             match window {
                 [prev, next] => push_frame(&mut output, next, Some(prev), &mut bit_widths, &mut scratch),
                 _ => panic!("This shouldn't happen")
@@ -80,7 +447,6 @@ fn check_cptv(path: &Path) -> std::io::Result<()> {
 
         //println!("8's {}, 16's {}", bit_widths[0], bit_widths[1]);
 
-        //println!("Packing took {:?}", Instant::now().duration_since(start));
         let start_gz = Instant::now();
 
         // TODO: Reuse this buffer?
@@ -94,74 +460,50 @@ fn check_cptv(path: &Path) -> std::io::Result<()> {
         let end = Instant::now();
 
         //std::fs::write("../cptv-files/out.cptv", &buffer);
-        println!("==== {:?}", &path);
-        println!("Frames: {}, Model: {:?}, original size {}, new size {}, savings {}x", frames.len(), v2_header.model, original_size, buffer.len(), original_size as f32 / buffer.len() as f32);
-        println!("Timings: packing {:?}, gz {:?}, total {:?} pack fps {}, fps total {}",
-                 start_gz.duration_since(start),
-                 end.duration_since(start_gz),
-                 end.duration_since(start),
-                 frames.len() as f64 / (start_gz.duration_since(start)).as_secs_f64(),
-                 frames.len() as f64 / (end.duration_since(start)).as_secs_f64()
-        );
-        // TODO: Test with arbitrary 16bit noise
-
-        // let (original_size, (header2, frames2)) = decode_buffer(&output)?;
-        //
-        // if let V2(header2) = header2 {
-        //     assert_eq!(v2_header.width, header2.width);
-        //     assert_eq!(v2_header.height, header2.height);
-        //     assert_eq!(frames.len(), frames2.len());
-        //     // Make sure this is equal to the original input (once decoded).
-        //     for (frame_num, (original, new)) in frames.iter().zip(frames2.iter()).enumerate() {
-        //         for y in 0..header2.height as usize {
-        //             for x in 0..header2.width as usize {
-        //                 let a = original.image_data[y][x];
-        //                 let b = new.image_data[y][x];
-        //                 assert_eq!(a, b, "Failed equality test on frame #{}, @{},{} {}/{}", frame_num, x, y, original.bit_width, new.bit_width);
-        //             }
-        //         }
-        //         // for (i, (a, b)) in original.image_data.data().iter().zip(new.image_data.data().iter()).enumerate() {
-        //         //     assert_eq!(*a, *b, "Failed equality test on frame #{}, px {} {}/{}", frame_num, i, original.bit_width, new.bit_width);
-        //         // }
-        //     }
-        // }
+        let report = verify_roundtrip(&original_bytes, &buffer);
+        if !report.is_identical() {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Re-encoding isn't lossless: {}", report)));
+        }
+        Ok(FileStats {
+            frames: frames.len(),
+            model: v2_header.model.clone(),
+            truncated_at,
+            original_size: original_bytes.len(),
+            reencoded_size: buffer.len(),
+            raw_size: v2_header.width as usize * v2_header.height as usize * 2 * frames.len(),
+            decode,
+            pack: start_gz.duration_since(start),
+            gz: end.duration_since(start_gz),
+        })
+    } else {
+        Err(Error::new(ErrorKind::Unsupported, "Only CPTV v2 files are supported"))
     }
-    Ok(())
 }
 
 
-fn decode_buffer(buffer: &Vec<u8>) -> Result<(CptvHeader, Vec<CptvFrame>), Error> {
-    if let Ok((mut body, header)) = decoder::decode_cptv_header(buffer) {
-        match header {
-            V2(header) => {
-                let mut frames = Vec::new();
-                while let Ok((rest, (image, mut frame))) = decode_frame_header_v2(body, header.width as usize, header.height as usize) {
-                    let last = frames.pop();
-                    unpack_frame_v2(&last, image, &mut frame);
-                    if let Some(last) = last {
-                        frames.push(last);
-                    }
-                    frames.push(frame);
-                    body = rest;
-                }
-                Ok((CptvHeader::V2(header), frames))
-            }
-            _ => unimplemented!()
-        }
-    } else {
-        Err(Error::new(ErrorKind::Other, "Oops"))
-    }
+struct DecodedFile {
+    bytes: Vec<u8>,
+    header: CptvHeader,
+    frames: Vec<CptvFrame>,
+    truncated_at: Option<usize>,
 }
 
-fn decode_file(file_path: &Path) -> Result<(usize, (CptvHeader, Vec<CptvFrame>)), Error> {
+fn decode_file(file_path: &Path) -> Result<DecodedFile, Error> {
     let mut file = File::open(file_path)?;
     let mut raw_buffer = Vec::new();
     file.read_to_end(&mut raw_buffer)?;
-    let mut buffer = Vec::new();
-    let start = Instant::now();
-    let mut decoder = GzDecoder::new(&*raw_buffer);
-    decoder.read_to_end(&mut buffer);
-    //println!("Unzip took {:?}", Instant::now().duration_since(start));
-    let result = decode_buffer(&buffer)?;
-    Ok((raw_buffer.len(), result))
+    let mut reader = CptvReader::new(&*raw_buffer)?;
+    let mut frames = (&mut reader).collect::<Result<Vec<_>, _>>()?;
+    // Re-encoding needs the background frame back in its place at the start of the stream.
+    if let Some(background) = reader.background() {
+        frames.insert(0, background.clone());
+    }
+    let header = V2(reader.header().clone());
+    let truncated_at = reader.truncated_at();
+    Ok(DecodedFile {
+        bytes: raw_buffer,
+        header,
+        frames,
+        truncated_at,
+    })
 }
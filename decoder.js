@@ -76,12 +76,31 @@ export class CptvDecoder {
     return await this.waitForMessage(type);
   }
 
+  async getBytesClipStats(arrayBuffer) {
+    await this.init();
+    const type = "getBytesClipStats";
+    decoder.postMessage({ type, arrayBuffer });
+    return await this.waitForMessage(type);
+  }
+
+  async getClipStats() {
+    const type = "getClipStats";
+    decoder.postMessage({ type });
+    return await this.waitForMessage(type);
+  }
+
   async getNextFrame() {
     const type = "getNextFrame";
     decoder.postMessage({ type });
     return await this.waitForMessage(type);
   }
 
+  async getBackgroundFrame() {
+    const type = "getBackgroundFrame";
+    decoder.postMessage({ type });
+    return await this.waitForMessage(type);
+  }
+
   async getTotalFrames() {
     const type = "getTotalFrames";
     decoder.postMessage({type});
@@ -112,6 +131,70 @@ export class CptvDecoder {
     return await this.waitForMessage(type);
   }
 
+  async getTimingReport() {
+    const type = "getTimingReport";
+    decoder.postMessage({type});
+    return await this.waitForMessage(type);
+  }
+
+  async getFrameTemperatures() {
+    const type = "getFrameTemperatures";
+    decoder.postMessage({type});
+    return await this.waitForMessage(type);
+  }
+
+  async getRegionTemperatureStats(x, y, width, height) {
+    const type = "getRegionTemperatureStats";
+    decoder.postMessage({type, x, y, width, height});
+    return await this.waitForMessage(type);
+  }
+
+  async getTruncatedAt() {
+    const type = "getTruncatedAt";
+    decoder.postMessage({type});
+    return await this.waitForMessage(type);
+  }
+
+  async setRecoveryMode(enabled) {
+    await this.init();
+    const type = "setRecoveryMode";
+    decoder.postMessage({type, enabled});
+    return await this.waitForMessage(type);
+  }
+
+  async setMotionDetection(options) {
+    await this.init();
+    const type = "setMotionDetection";
+    decoder.postMessage({type, options});
+    return await this.waitForMessage(type);
+  }
+
+  async setCollectStats(enabled) {
+    await this.init();
+    const type = "setCollectStats";
+    decoder.postMessage({type, enabled});
+    return await this.waitForMessage(type);
+  }
+
+  async setMinMaxExclusions(exclusions) {
+    await this.init();
+    const type = "setMinMaxExclusions";
+    decoder.postMessage({type, exclusions});
+    return await this.waitForMessage(type);
+  }
+
+  async getTracks() {
+    const type = "getTracks";
+    decoder.postMessage({type});
+    return await this.waitForMessage(type);
+  }
+
+  async getSkippedRanges() {
+    const type = "getSkippedRanges";
+    decoder.postMessage({type});
+    return await this.waitForMessage(type);
+  }
+
   async free() {
     const type = "freeResources";
     if (decoder) {
@@ -65,6 +65,28 @@ const FakeReader = function (bytes, maxChunkSize = 0) {
   }
 };
 
+// Methods this interface relies on that older builds of the wasm package don't have.
+const REQUIRED_METHODS = [
+  "getFrameTimestamp",
+  "getBackgroundFrame",
+  "setRecoveryMode",
+  "getSkippedRanges",
+  "getTruncatedAt",
+  "setMotionDetection",
+  "disableMotionDetection",
+  "getMotionMask",
+  "getMotionScore",
+  "getFrameTracks",
+  "getTracks",
+  "setMinMaxExclusion",
+  "clearMinMaxExclusions",
+  "setCollectStats",
+  "getClipStats",
+  "getTimingReport",
+  "getFrameTemperatures",
+  "getRegionTemperatureStats",
+];
+
 // TODO(jon): This differs depending on whether the sensor is lepton 3 or 3.5
 // TODO(jon): This is probably out of scope for this library, should be handled
 //  at the player level.
@@ -86,6 +108,20 @@ class CptvDecoderInterface {
     return this.playerContext && this.playerContext.ptr;
   }
 
+  // The wasm package in decoder/pkg is prebuilt, and may predate some of the methods used here.
+  // Refuse to use it rather than quietly returning empty results for the missing ones.
+  checkWasmPackage() {
+    const missing = REQUIRED_METHODS.filter((method) => typeof this.playerContext[method] !== "function");
+    if (typeof CptvPlayerContext.fetchBackgroundFrame !== "function") {
+      missing.push("fetchBackgroundFrame");
+    }
+    if (missing.length) {
+      this.playerContext.free();
+      delete this.playerContext;
+      throw new Error(`The decoder wasm package is too old (missing ${missing.join(", ")}), rebuild it with build-decoder.sh`);
+    }
+  }
+
   async initWithCptvUrlAndSize(url, size) {
     this.free();
     const unlocker = new Unlocker();
@@ -104,6 +140,11 @@ class CptvDecoderInterface {
         this.expectedSize = size;
         await initWasm();
         this.playerContext = await CptvPlayerContext.newWithStream(this.reader);
+        this.checkWasmPackage();
+        this.applyRecoveryMode();
+        this.applyMotionDetection();
+        this.applyMinMaxExclusions();
+        this.applyCollectStats();
         unlocker.unlock();
         this.inited = true;
         this.locked = false;
@@ -139,6 +180,11 @@ class CptvDecoderInterface {
     try {
       await initWasm();
       this.playerContext = await CptvPlayerContext.newWithStream(this.reader);
+      this.checkWasmPackage();
+      this.applyRecoveryMode();
+      this.applyMotionDetection();
+      this.applyMinMaxExclusions();
+      this.applyCollectStats();
       this.inited = true;
       result = true;
     } catch (e) {
@@ -181,6 +227,9 @@ class CptvDecoderInterface {
     }
     const frameData = this.playerContext.getNextFrame();
     const frameHeader = this.playerContext.getFrameHeader();
+    if (frameHeader) {
+      frameHeader.timestampMs = this.playerContext.getFrameTimestamp();
+    }
     // NOTE(jon): Work around a bug where the mlx sensor doesn't report timeOn times, just hardcodes 60000
     if (frameHeader && frameHeader.imageData.width !== 32) {
       const sameFrameAsPrev = frameHeader && this.prevFrameHeader && frameHeader.timeOnMs === this.prevFrameHeader.timeOnMs;
@@ -196,9 +245,43 @@ class CptvDecoderInterface {
       return null;
     }
     this.framesRead++;
+    if (this.motionDetection) {
+      const mask = this.playerContext.getMotionMask();
+      if (mask) {
+        return { data: new Uint16Array(frameData), meta: frameHeader, motion: { mask, score: this.playerContext.getMotionScore(), tracks: this.playerContext.getFrameTracks() } };
+      }
+    }
     return { data: new Uint16Array(frameData), meta: frameHeader };
   }
 
+  async getBackgroundFrame() {
+    if (!this.reader) {
+      console.warn("You need to initialise the player with the url of a CPTV file");
+      return null;
+    }
+    if (this.consumed || !this.hasValidContext()) {
+      return null;
+    }
+    const unlocker = new Unlocker();
+    await this.lockIsUncontended(unlocker);
+    this.locked = true;
+    try {
+      this.playerContext = await CptvPlayerContext.fetchBackgroundFrame(this.playerContext);
+    } catch (e) {
+      this.streamError = e;
+    }
+    unlocker.unlock();
+    this.locked = false;
+    if (this.hasStreamError()) {
+      return null;
+    }
+    const frameData = this.playerContext.getBackgroundFrame();
+    if (!frameData) {
+      return null;
+    }
+    return { data: frameData, meta: this.playerContext.getBackgroundFrameHeader() };
+  }
+
   async countTotalFrames() {
     if (!this.reader) {
       console.warn("You need to initialise the player with the url of a CPTV file");
@@ -234,10 +317,12 @@ class CptvDecoderInterface {
         totalFrameCount = await this.countTotalFrames();
       }
       const duration = (1 / header.fps) * totalFrameCount;
+      const truncatedAt = this.getTruncatedAt();
       return {
         ...header,
         duration,
         totalFrames: totalFrameCount,
+        ...(truncatedAt !== null && { truncated: true, truncatedAt }),
       }
     }
   }
@@ -247,6 +332,39 @@ class CptvDecoderInterface {
     return await this.getMetadata();
   }
 
+  async getBytesClipStats(fileBytes) {
+    await this.initWithFileBytes(fileBytes, "", typeof __ENV__ === "undefined");
+    if (this.hasValidContext()) {
+      // Only for this file, so it doesn't change the setting for later files.
+      this.playerContext.setCollectStats(true);
+    }
+    while (await this.fetchNextFrame() !== null) {
+      // Stats are gathered as each frame is decoded.
+    }
+    if (this.hasStreamError()) {
+      return this.streamError;
+    }
+    return this.getClipStats();
+  }
+
+  setCollectStats(enabled) {
+    this.collectStats = enabled;
+    if (this.hasValidContext()) {
+      this.applyCollectStats();
+    }
+  }
+
+  applyCollectStats() {
+    this.playerContext.setCollectStats(!!this.collectStats);
+  }
+
+  getClipStats() {
+    if (!this.hasValidContext()) {
+      return null;
+    }
+    return this.playerContext.getClipStats();
+  }
+
   async getStreamMetadata(url, size) {
     await this.initWithCptvUrlAndSize(url, size);
     return await this.getMetadata();
@@ -303,6 +421,92 @@ class CptvDecoderInterface {
   hasStreamError() {
     return this.streamError !== undefined;
   }
+
+  getTimingReport() {
+    if (!this.hasValidContext()) {
+      return null;
+    }
+    return this.playerContext.getTimingReport();
+  }
+
+  getFrameTemperatures() {
+    if (!this.hasValidContext()) {
+      return null;
+    }
+    return this.playerContext.getFrameTemperatures();
+  }
+
+  getRegionTemperatureStats(x, y, width, height) {
+    if (!this.hasValidContext()) {
+      return null;
+    }
+    return this.playerContext.getRegionTemperatureStats(x, y, width, height);
+  }
+
+  getTruncatedAt() {
+    if (!this.hasValidContext()) {
+      return null;
+    }
+    return this.playerContext.getTruncatedAt();
+  }
+
+  setRecoveryMode(enabled) {
+    this.recoveryMode = enabled;
+    if (this.hasValidContext()) {
+      this.applyRecoveryMode();
+    }
+  }
+
+  applyRecoveryMode() {
+    this.playerContext.setRecoveryMode(!!this.recoveryMode);
+  }
+
+  setMotionDetection(options) {
+    this.motionDetection = options;
+    if (this.hasValidContext()) {
+      return this.applyMotionDetection();
+    }
+    return true;
+  }
+
+  applyMotionDetection() {
+    if (!this.motionDetection) {
+      this.playerContext.disableMotionDetection();
+      return true;
+    }
+    const {model = "stored", threshold = 50, dilation = 1, alpha = 0.05} = this.motionDetection;
+    return this.playerContext.setMotionDetection(model, threshold, dilation, alpha);
+  }
+
+  setMinMaxExclusions(exclusions) {
+    this.minMaxExclusions = exclusions;
+    if (this.hasValidContext()) {
+      this.applyMinMaxExclusions();
+    }
+  }
+
+  applyMinMaxExclusions() {
+    this.playerContext.clearMinMaxExclusions();
+    for (const {model, border = 0, mask} of this.minMaxExclusions || []) {
+      const {top = 0, right = 0, bottom = 0, left = 0} = typeof border === "number" ?
+        {top: border, right: border, bottom: border, left: border} : border;
+      this.playerContext.setMinMaxExclusion(model, top, right, bottom, left, mask);
+    }
+  }
+
+  getTracks() {
+    if (!this.hasValidContext()) {
+      return null;
+    }
+    return this.playerContext.getTracks();
+  }
+
+  getSkippedRanges() {
+    if (!this.hasValidContext()) {
+      return [];
+    }
+    return this.playerContext.getSkippedRanges();
+  }
 }
 
 const context = typeof self !== "undefined" ? self : parentPort;
@@ -329,6 +533,16 @@ context.addEventListener("message", async ({data}) => {
       context.postMessage({type: data.type, data: header});
     }
       break;
+    case "getBytesClipStats": {
+      const stats = await player.getBytesClipStats(data.arrayBuffer);
+      context.postMessage({type: data.type, data: stats});
+    }
+      break;
+    case "getClipStats": {
+      const stats = player.getClipStats();
+      context.postMessage({type: data.type, data: stats});
+    }
+      break;
     case "getStreamMetadata": {
       const header = await player.getStreamMetadata(data.url);
       context.postMessage({type: data.type, data: header});
@@ -339,6 +553,11 @@ context.addEventListener("message", async ({data}) => {
       context.postMessage({type: data.type, data: frame});
     }
       break;
+    case "getBackgroundFrame": {
+      const frame = await player.getBackgroundFrame();
+      context.postMessage({type: data.type, data: frame});
+    }
+      break;
     case "getTotalFrames": {
       const totalFrames = player.getTotalFrames();
       context.postMessage({type: data.type, data: totalFrames});
@@ -364,6 +583,56 @@ context.addEventListener("message", async ({data}) => {
       context.postMessage({type: data.type, data: error });
     }
       break;
+    case "setRecoveryMode": {
+      player.setRecoveryMode(data.enabled);
+      context.postMessage({type: data.type, data: true });
+    }
+      break;
+    case "setMotionDetection": {
+      const result = player.setMotionDetection(data.options);
+      context.postMessage({type: data.type, data: result });
+    }
+      break;
+    case "setCollectStats": {
+      player.setCollectStats(data.enabled);
+      context.postMessage({type: data.type, data: true });
+    }
+      break;
+    case "setMinMaxExclusions": {
+      player.setMinMaxExclusions(data.exclusions);
+      context.postMessage({type: data.type, data: true });
+    }
+      break;
+    case "getTracks": {
+      const tracks = player.getTracks();
+      context.postMessage({type: data.type, data: tracks });
+    }
+      break;
+    case "getSkippedRanges": {
+      const ranges = player.getSkippedRanges();
+      context.postMessage({type: data.type, data: ranges });
+    }
+      break;
+    case "getTimingReport": {
+      const report = player.getTimingReport();
+      context.postMessage({type: data.type, data: report });
+    }
+      break;
+    case "getFrameTemperatures": {
+      const temperatures = player.getFrameTemperatures();
+      context.postMessage({type: data.type, data: temperatures });
+    }
+      break;
+    case "getRegionTemperatureStats": {
+      const stats = player.getRegionTemperatureStats(data.x, data.y, data.width, data.height);
+      context.postMessage({type: data.type, data: stats });
+    }
+      break;
+    case "getTruncatedAt": {
+      const truncatedAt = player.getTruncatedAt();
+      context.postMessage({type: data.type, data: truncatedAt });
+    }
+      break;
     case "freeResources": {
       player.free();
       context.postMessage({type: data.type, data: true });
@@ -0,0 +1,206 @@
+use crate::streaming::{Decoded, StreamingDecoder};
+use cptv_shared::v2::types::{Cptv2Header, CptvFrame, ModelExclusions};
+use futures_core::Stream;
+use futures_io::AsyncRead;
+use std::future::poll_fn;
+use std::io;
+use std::io::ErrorKind;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Decodes a gzipped CPTV v2 clip from an `AsyncRead`, such as an upload that is still arriving,
+/// yielding its frames as a `Stream`.  Tokio readers can be adapted with `tokio_util::compat`.
+///
+/// Behaves like `CptvReader`: a background frame isn't one of the frames of the stream (see
+/// `background()`), and a stream that is cut off still yields every complete frame before the
+/// cut.
+pub struct AsyncCptvReader<R: AsyncRead + Unpin> {
+    inner: R,
+    decoder: StreamingDecoder,
+    read_buffer: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncCptvReader<R> {
+    pub fn new(inner: R) -> AsyncCptvReader<R> {
+        AsyncCptvReader::with_min_max_exclusions(inner, ModelExclusions::default())
+    }
+
+    /// Like `new`, but leaving the given pixels out of each frame's min and max, depending on
+    /// the camera model.
+    pub fn with_min_max_exclusions(inner: R, exclusions: ModelExclusions) -> AsyncCptvReader<R> {
+        AsyncCptvReader {
+            inner,
+            decoder: StreamingDecoder::with_min_max_exclusions(exclusions),
+            read_buffer: vec![0; READ_CHUNK_SIZE],
+        }
+    }
+
+    /// Reads as far as the end of the clip header, which is all that's needed for its metadata.
+    pub async fn header(&mut self) -> io::Result<&Cptv2Header> {
+        poll_fn(|cx| self.poll_header(cx)).await?;
+        Ok(self.decoder.header().unwrap())
+    }
+
+    /// Decodes the next frame, returning `None` once there are no more complete frames.
+    pub async fn next_frame(&mut self) -> io::Result<Option<&CptvFrame>> {
+        match poll_fn(|cx| self.poll_frame(cx)).await? {
+            true => Ok(self.decoder.frame()),
+            false => Ok(None),
+        }
+    }
+
+    /// The background frame, if the clip has one and it has been read.
+    pub fn background(&self) -> Option<&CptvFrame> {
+        self.decoder.background()
+    }
+
+    /// Number of frames decoded so far, not counting any background frame.
+    pub fn frame_count(&self) -> usize {
+        self.decoder.frame_count()
+    }
+
+    /// If the stream was cut off, the offset into the decompressed stream at which the first
+    /// incomplete frame starts.  Only known once all the complete frames have been read.
+    pub fn truncated_at(&self) -> Option<usize> {
+        self.decoder.truncated_at()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn poll_header(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.decoder.header().is_none() {
+            match self.decoder.decode()? {
+                Decoded::NeedBytes => {
+                    std::task::ready!(self.poll_fill(cx))?;
+                }
+                Decoded::End => {
+                    return Poll::Ready(Err(io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "Stream ends inside the CPTV header",
+                    )))
+                }
+                _ => {}
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    // Ready with true once the next frame is available from the decoder, or false at the end.
+    fn poll_frame(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<bool>> {
+        loop {
+            match self.decoder.decode()? {
+                Decoded::Header => {}
+                Decoded::Frame => return Poll::Ready(Ok(true)),
+                Decoded::End => return Poll::Ready(Ok(false)),
+                Decoded::NeedBytes => {
+                    std::task::ready!(self.poll_fill(cx))?;
+                }
+            }
+        }
+    }
+
+    // Passes the next chunk read from the inner reader on to the decoder.
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.decoder.stream_ended() {
+            return Poll::Ready(Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "Decoder wants more bytes after the end of the stream",
+            )));
+        }
+        loop {
+            match Pin::new(&mut self.inner).poll_read(cx, &mut self.read_buffer) {
+                Poll::Ready(Ok(0)) => self.decoder.end_stream(),
+                Poll::Ready(Ok(read_bytes)) => self.decoder.push(&self.read_buffer[..read_bytes]),
+                Poll::Ready(Err(e)) if e.kind() == ErrorKind::Interrupted => continue,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+            return Poll::Ready(Ok(()));
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for AsyncCptvReader<R> {
+    type Item = io::Result<CptvFrame>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let reader = self.get_mut();
+        match std::task::ready!(reader.poll_frame(cx)) {
+            Ok(true) => Poll::Ready(reader.decoder.frame().cloned().map(Ok)),
+            Ok(false) => Poll::Ready(None),
+            Err(e) => Poll::Ready(Some(Err(e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::CptvReader;
+    use crate::streaming::tests::test_cptv_file;
+    use futures_executor::block_on;
+
+    // Hands out at most `chunk_size` bytes per read, and is only ready on every other poll.
+    struct Trickle {
+        data: Vec<u8>,
+        position: usize,
+        chunk_size: usize,
+        ready: bool,
+    }
+
+    impl AsyncRead for Trickle {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let length = self
+                .chunk_size
+                .min(buf.len())
+                .min(self.data.len() - self.position);
+            buf[..length].copy_from_slice(&self.data[self.position..self.position + length]);
+            self.position += length;
+            Poll::Ready(Ok(length))
+        }
+    }
+
+    #[test]
+    fn reads_a_clip_arriving_in_chunks() {
+        let file = test_cptv_file(5);
+        let expected: Vec<_> = CptvReader::new(&file[..])
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        for chunk_size in [1, 7, 100, file.len()] {
+            let mut reader = AsyncCptvReader::new(Trickle {
+                data: file.clone(),
+                position: 0,
+                chunk_size,
+                ready: false,
+            });
+            block_on(async {
+                assert_eq!(reader.header().await.unwrap().width, 16);
+                let mut frames = Vec::new();
+                while let Some(frame) = reader.next_frame().await.unwrap() {
+                    frames.push(frame.clone());
+                }
+                assert_eq!(frames.len(), expected.len());
+                for (frame, expected) in frames.iter().zip(&expected) {
+                    assert_eq!(frame.image_data.data(), expected.image_data.data());
+                    assert_eq!(frame.time_on, expected.time_on);
+                }
+            });
+            assert!(reader.background().is_some());
+            assert_eq!(reader.frame_count(), 5);
+        }
+    }
+}
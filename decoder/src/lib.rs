@@ -1,85 +1,28 @@
-use js_sys::{Reflect, Uint16Array, Uint8Array};
-use log::{error, Level};
+use js_sys::{Float32Array, Reflect, Uint16Array, Uint8Array};
+use log::Level;
 #[allow(unused)]
 use log::{info, trace, warn};
-use std::io::{ErrorKind, Read};
 use wasm_bindgen::prelude::*;
 
-use libflate::non_blocking::gzip::Decoder;
-use std::collections::VecDeque;
 use std::io;
+use std::io::ErrorKind;
 use wasm_bindgen::JsCast;
-use cptv_shared::v2::{decode_frame_header_v2, unpack_frame_v2};
-use cptv_shared::v2::types::CptvFrame;
-use cptv_shared::CptvHeader;
-use crate::decoder::decode_cptv_header;
-
+use cptv_shared::v2::calibration::Calibration;
+use cptv_shared::v2::motion::{BackgroundModel, MotionDetector, MotionFrame, MotionParams};
+use cptv_shared::v2::stats::{ClipStatsAccumulator, ClipStatsOptions};
+use cptv_shared::v2::timing::{analyse_timing, ClipClock};
+use cptv_shared::v2::tracking::{FrameTrack, Tracker, TrackerParams};
+use cptv_shared::v2::types::{Cptv2Header, EdgeBorder, MinMaxExclusion, ModelExclusions};
+use crate::streaming::{Decoded, StreamingDecoder};
+
+#[cfg(feature = "async")]
+pub mod async_reader;
 pub mod decoder;
-
-struct DownloadedData {
-    gz_decoded: VecDeque<u8>,
-    gz_ended: bool,
-    parse_error: bool,
-    num_decompressed_bytes: usize,
-}
-
-impl DownloadedData {
-    pub fn new() -> DownloadedData {
-        DownloadedData {
-            gz_decoded: VecDeque::new(),
-            gz_ended: false,
-            parse_error: false,
-            num_decompressed_bytes: 0,
-        }
-    }
-}
-
-struct ResumableReader {
-    inner: VecDeque<u8>,
-    loaded_bytes: usize,
-    stream_ended: bool,
-}
-
-impl ResumableReader {
-    pub fn new() -> ResumableReader {
-        ResumableReader {
-            inner: VecDeque::new(),
-            loaded_bytes: 0,
-            stream_ended: false,
-        }
-    }
-
-    pub fn len(&self) -> usize {
-        self.inner.len()
-    }
-
-    pub fn append(&mut self, bytes: &Uint8Array) {
-        assert!(bytes.byte_length() == bytes.length());
-        self.inner.append(&mut VecDeque::from(bytes.to_vec()));
-        self.loaded_bytes += bytes.length() as usize;
-    }
-}
-
-impl Read for ResumableReader {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if self.len() < buf.len() && !self.stream_ended {
-            Err(io::Error::new(
-                io::ErrorKind::WouldBlock,
-                "Waiting for more bytes from stream",
-            ))
-        } else {
-            for i in 0..buf.len() {
-                match self.inner.pop_front() {
-                    Some(byte) => buf[i] = byte,
-                    None => {
-                        return Ok(i)
-                    },
-                }
-            }
-            Ok(buf.len())
-        }
-    }
-}
+pub mod reader;
+pub mod streaming;
+pub mod thumbnail;
+pub mod validate;
+pub mod verify;
 
 #[wasm_bindgen]
 extern "C" {
@@ -93,28 +36,39 @@ extern "C" {
 
 #[wasm_bindgen]
 pub struct CptvPlayerContext {
-    /// Holds information about current downloaded file data
-    downloaded_data: DownloadedData,
-
-    /// Current clip metadata
-    header_info: CptvHeader, // TODO(jon): Are we okay with doing our dynamic dispatch off of this enum?
+    /// Decodes the clip from the bytes read from the stream so far.
+    decoder: StreamingDecoder,
 
-    last_time_on: usize,
-    frame_buffer: Option<CptvFrame>,
-    frame_count: usize,
+    /// Set when the first frame of the clip was decoded while looking for the background frame,
+    /// and hasn't been returned yet.
+    frame_pending: bool,
 
     // TODO(jon): Can we make this implement the Read trait?
     reader: Option<ReadableStreamDefaultReader>,
-    gz_buffer: Vec<u8>,
-    gz_decoder: Option<Decoder<ResumableReader>>,
+
+    /// time_on of every frame read so far, excluding any background frame.
+    times_on: Vec<u32>,
+
+    /// Motion detection settings, if enabled.
+    motion_settings: Option<(BackgroundModel, MotionParams)>,
+    motion_detector: Option<MotionDetector>,
+    /// Motion in the current frame.
+    motion: Option<MotionFrame>,
+    /// Tracks of the foreground regions found by motion detection.
+    tracker: Option<Tracker>,
+
+    /// Whether to gather statistics over the frames fetched, which costs a little per frame.
+    collect_stats: bool,
+    /// Statistics over the frames fetched so far.
+    clip_stats: Option<ClipStatsAccumulator>,
+
+    /// Pixels to leave out of each frame's min and max, by camera model.
+    min_max_exclusions: ModelExclusions,
 }
 
 fn init_console() {
     console_error_panic_hook::set_once();
-    let _ = match console_log::init_with_level(Level::Warn) {
-        Ok(x) => x,
-        Err(_) => (),
-    };
+    let _ = console_log::init_with_level(Level::Warn);
 }
 
 fn has_gz_header(bytes: &[u8]) -> bool {
@@ -132,25 +86,24 @@ impl CptvPlayerContext {
         // Init the console logging stuff on startup, so that wasm can print things
         // into the browser console.
         let mut context = CptvPlayerContext {
-            downloaded_data: DownloadedData::new(),
-            gz_decoder: None,
-            header_info: CptvHeader::UNINITIALISED,
-            frame_buffer: None,
-            frame_count: 0,
-            last_time_on: 0,
+            decoder: StreamingDecoder::new(),
+            frame_pending: false,
             reader: Some(stream),
-            gz_buffer: vec![0; 160 * 120 * 2],
+            times_on: Vec::new(),
+            motion_settings: None,
+            motion_detector: None,
+            motion: None,
+            tracker: None,
+            collect_stats: false,
+            clip_stats: None,
+            min_max_exclusions: ModelExclusions::default(),
         };
-        let mut reader = ResumableReader::new();
         // Do the initial read from the stream
-        let mut stream_ended = false;
-
-        while reader.len() < 2 && !stream_ended {
+        while context.decoder.pending_input().len() < 2 && !context.decoder.stream_ended() {
             // Make sure we get at least two bytes, or fail if the stream is shorter
-            stream_ended = context.get_bytes_from_stream(Some(&mut reader)).await?;
+            context.get_bytes_from_stream().await?;
         }
-        if has_gz_header(reader.inner.as_slices().0) {
-            context.gz_decoder = Some(Decoder::new(reader));
+        if has_gz_header(context.decoder.pending_input().inner.as_slices().0) {
             Ok(context)
         } else {
             Err(JsValue::from(
@@ -159,34 +112,24 @@ impl CptvPlayerContext {
         }
     }
 
-    /// Reads bytes from readable stream, and appends them to the available bytes for the streaming
-    /// gzip decoder.
-    async fn get_bytes_from_stream(
-        &mut self,
-        target: Option<&mut ResumableReader>,
-    ) -> Result<bool, JsValue> {
+    /// Reads bytes from readable stream, and passes them on to the decoder.
+    async fn get_bytes_from_stream(&mut self) -> Result<bool, JsValue> {
         let result = wasm_bindgen_futures::JsFuture::from(self.read_from_stream()).await?;
-        let sink = target.unwrap_or_else(|| self.reader_mut());
-        sink.stream_ended = Reflect::get(&result, &JsValue::from_str("done"))
+        let done = Reflect::get(&result, &JsValue::from_str("done"))
             .expect("Should have property 'done'")
             .as_bool()
             .unwrap();
-
         if let Ok(value) = Reflect::get(&result, &JsValue::from_str("value")) {
             if !value.is_undefined() {
                 let arr = value.dyn_into::<Uint8Array>().unwrap();
-                sink.append(&arr);
+                assert!(arr.byte_length() == arr.length());
+                self.decoder.push(&arr.to_vec());
             }
         }
-        Ok(sink.stream_ended)
-    }
-
-    fn reader_mut(&mut self) -> &mut ResumableReader {
-        self.gz_decoder.as_mut().unwrap().as_inner_mut()
-    }
-
-    fn reader(&self) -> &ResumableReader {
-        self.gz_decoder.as_ref().unwrap().as_inner_ref()
+        if done {
+            self.decoder.end_stream();
+        }
+        Ok(self.decoder.stream_ended())
     }
 
     fn read_from_stream(&self) -> js_sys::Promise {
@@ -199,40 +142,44 @@ impl CptvPlayerContext {
         }
     }
 
-    fn pump_gz(&mut self) -> io::Result<usize> {
-        let read_bytes = self.gz_decoder.as_mut().unwrap().read(&mut self.gz_buffer);
-        match &read_bytes {
-            Ok(read_bytes) => {
-                for i in 0..*read_bytes {
-                    self.downloaded_data.gz_decoded.push_back(self.gz_buffer[i]);
+    /// Decodes the header, or the next frame, reading more of the stream as it's needed.  The
+    /// outer error is from reading the stream, and the inner one from decoding it.
+    async fn decode_next(&mut self, unpack_frame: bool) -> Result<io::Result<Decoded>, JsValue> {
+        loop {
+            let decoded = match unpack_frame {
+                true => self.decoder.decode(),
+                false => self.decoder.skip_frame(),
+            };
+            match decoded {
+                Ok(Decoded::NeedBytes) if !self.decoder.stream_ended() => {
+                    self.get_bytes_from_stream().await?;
                 }
-            }
-            Err(e) => {
-                match e.kind() {
-                ErrorKind::WouldBlock => {}
-                _ => {
-                    warn!("Gzip stream error {:?}", e);
-                    self.downloaded_data.parse_error = true;
+                Ok(Decoded::NeedBytes) => {
+                    return Ok(Err(io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "Decoder wants more bytes after the end of the stream",
+                    )))
+                }
+                Ok(Decoded::Frame) => {
+                    if let Some(frame) = self.decoder.frame() {
+                        self.times_on.push(frame.time_on);
+                    }
+                    return Ok(Ok(Decoded::Frame));
                 }
+                decoded => return Ok(decoded),
             }
-            },
         }
-
-        read_bytes
     }
 
+    /// True once every frame in the stream has been read.
     #[wasm_bindgen(js_name = streamComplete)]
     pub fn stream_complete(&self) -> bool {
-        self.reader().stream_ended && self.downloaded_data.gz_ended
-    }
-
-    fn decode_ended(&self) -> bool {
-        self.downloaded_data.gz_ended || self.downloaded_data.parse_error
+        self.decoder.finished()
     }
 
     fn total_frames(&self) -> Option<usize> {
         if self.stream_complete() {
-            Some(self.frame_count)
+            Some(self.decoder.frame_count())
         } else {
             None
         }
@@ -242,321 +189,443 @@ impl CptvPlayerContext {
     pub async fn count_frames(
         mut context: CptvPlayerContext,
     ) -> Result<CptvPlayerContext, JsValue> {
-        while !context.decode_ended() {
-            // Load until we have the frame.
-            context = CptvPlayerContext::parse_next_frame(context, false).await?;
-        }
-
-        info!(
-            "Mem usage :: Reader {}, Decoded {}, frames {}",
-            context.reader_mut().inner.capacity(),
-            context.downloaded_data.gz_decoded.capacity(),
-            context.frame_count
-        );
-        if context.downloaded_data.parse_error {
-            Err(JsValue::from_str("Invalid or corrupted CPTV stream"))
-        } else {
-            Ok(context)
-        }
+        // Only the frame headers are needed to count the frames.
+        while context.decode_next(false).await?.map_err(stream_error)? != Decoded::End {}
+        Ok(context)
     }
 
     #[wasm_bindgen(js_name = fetchNextFrame)]
     pub async fn fetch_next_frame(
         mut context: CptvPlayerContext,
     ) -> Result<CptvPlayerContext, JsValue> {
-        let prev_frame_count = context.frame_count;
-        let mut last_poll = prev_frame_count;
-        while context.frame_count == prev_frame_count && !context.reader().stream_ended {
-            // We didn't get the frame, so poll again.
-            // warn!("Polling for frame {}", context.frame_count + 1);
-            if last_poll == context.frame_count + 1 {
-               error!("Polling again for {}", context.frame_count + 1);
-            } else {
-               last_poll = prev_frame_count + 1;
+        if context.frame_pending {
+            context.frame_pending = false;
+            context.frame_fetched();
+            return Ok(context);
+        }
+        loop {
+            match context.decode_next(true).await?.map_err(stream_error)? {
+                Decoded::Header => {}
+                Decoded::Frame => {
+                    context.frame_fetched();
+                    return Ok(context);
+                }
+                _ => return Ok(context),
             }
-            context = CptvPlayerContext::parse_next_frame(context, true).await?;
         }
-        if context.downloaded_data.parse_error {
-            Err(JsValue::from_str("Invalid or corrupted CPTV stream"))
-        } else {
-            Ok(context)
+    }
+
+    /// If the stream was truncated, the offset into the decompressed stream where the first
+    /// incomplete frame starts, otherwise null.
+    #[wasm_bindgen(js_name = getTruncatedAt)]
+    pub fn get_truncated_at(&self) -> JsValue {
+        match self.decoder.truncated_at() {
+            Some(offset) => JsValue::from_f64(offset as f64),
+            None => JsValue::null(),
         }
     }
 
-    async fn parse_next_frame(
-        mut context: CptvPlayerContext,
-        unpack_frame: bool,
-    ) -> Result<CptvPlayerContext, JsValue> {
-        // TODO(jon): Dispatch on CPTV version here
-        loop {
-            match context.header_info {
-                CptvHeader::UNINITIALISED => {
-                    context = CptvPlayerContext::fetch_header(context).await?;
-                }
-                _ => {
-                    // Try to parse a frame header:
-                    let width = context.get_width() as usize;
-                    let height = context.get_height() as usize;
-                    context.downloaded_data.gz_decoded.make_contiguous();
-                    let frame_data = context.downloaded_data.gz_decoded.as_slices().0;
-                    if frame_data.len() == 0 {
-                        let (ctx, _should_continue, bytes_read) =
-                            CptvPlayerContext::fetch_bytes(context).await?;
-                        context = ctx;
-                        if bytes_read == 0 || context.downloaded_data.parse_error {
-                            if bytes_read == 0 {
-                                context = CptvPlayerContext::fetch_bytes(context).await?.0;
-                            }
-                            break;
-                        }
-                        continue;
-                    }
-                    match decode_frame_header_v2(frame_data, width, height, false) {
-                        Ok((remaining, (frame_data, mut frame))) => {
-                            context.last_time_on = frame.time_on as usize;
-                            // Make sure there are enough bytes to decode another frame. width * height * (frame.bit_width / 8
-                            if unpack_frame {
-                                unpack_frame_v2(&context.frame_buffer, frame_data, &mut frame);
-                                // Store the decoded frame
-                                context.frame_buffer = Some(frame);
-                            }
-
-
-                            // Pop used bytes off the decoded buffer
-                            let remaining_size = remaining.len();
-                            while context.downloaded_data.gz_decoded.len() > remaining_size {
-                                context.downloaded_data.gz_decoded.pop_front();
-                            }
-                            // Increment the frame count
-                            context.frame_count += 1;
-
-                            break;
-                        }
-                        Err(e) => {
-                            match e {
-                                nom::Err::Incomplete(_) => {
-                                    assert!(!context.stream_complete());
-                                    // Fetch more bytes and loop again.
-                                    context = CptvPlayerContext::fetch_bytes(context).await?.0;
-                                }
-                                nom::Err::Error((_remaining, _kind))
-                                | nom::Err::Failure((_remaining, _kind)) => {
-                                    if context.stream_complete() {
-                                        // We're trying to read past the available frames.
-                                        // Now we know how many frames there actually were in the video,
-                                        // and can print that information.
-                                        info!(
-                                            "Stream completed with total frames {:?}{}",
-                                            context.total_frames().unwrap(),
-                                            if context.has_background_frame() {
-                                                " (plus 1 background frame)"
-                                            } else {
-                                                ""
-                                            }
-                                        );
-                                        break;
-                                    } else {
-                                        warn!("Decode frame data {}", frame_data.len());
-                                        decode_frame_header_v2(frame_data, width, height, true);
-                                        warn!("Parse error?? {:?}", _kind);
-                                        context.frame_count += 1;
-                                        context.downloaded_data.parse_error = true;
-                                        context.downloaded_data.gz_ended = true;
-                                        break;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-            if context.downloaded_data.parse_error {
-                break;
-            }
+    /// Enables skipping over corrupted frames to the next frame that can be decoded.  Frames
+    /// after a skipped range are decoded against the wrong frame, and have `decodedAfterSkip`
+    /// set, until the next intra frame; see `StreamingDecoder::set_recovery_mode`.
+    #[wasm_bindgen(js_name = setRecoveryMode)]
+    pub fn set_recovery_mode(&mut self, enabled: bool) {
+        self.decoder.set_recovery_mode(enabled);
+    }
+
+    /// Ranges of the decompressed stream skipped so far in recovery mode.
+    #[wasm_bindgen(js_name = getSkippedRanges)]
+    pub fn get_skipped_ranges(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.decoder.skipped_ranges().to_vec()).unwrap()
+    }
+
+    /// Leaves pixels out of each frame's min and max, for sensors with unreliable edge pixels.
+    /// Applies to frames from cameras of the given `model`, or to any other model if `model`
+    /// is not given.  `mask` has one byte per pixel in row order, where non-zero means ignore
+    /// the pixel.  Must be called before the frames are fetched.
+    #[wasm_bindgen(js_name = setMinMaxExclusion)]
+    pub fn set_min_max_exclusion(
+        &mut self,
+        model: Option<String>,
+        top: usize,
+        right: usize,
+        bottom: usize,
+        left: usize,
+        mask: Option<Uint8Array>,
+    ) {
+        let exclusion = MinMaxExclusion {
+            border: EdgeBorder {
+                top,
+                right,
+                bottom,
+                left,
+            },
+            mask: mask.map(|mask| mask.to_vec().iter().map(|pixel| *pixel != 0).collect()),
+        };
+        match model {
+            Some(model) => self.min_max_exclusions.set_for_model(&model, exclusion),
+            None => self.min_max_exclusions.set_default(exclusion),
         }
-        Ok(context)
+        self.decoder.set_min_max_exclusions(self.min_max_exclusions.clone());
+    }
+
+    #[wasm_bindgen(js_name = clearMinMaxExclusions)]
+    pub fn clear_min_max_exclusions(&mut self) {
+        self.min_max_exclusions = ModelExclusions::default();
+        self.decoder.set_min_max_exclusions(self.min_max_exclusions.clone());
+    }
+
+    fn header(&self) -> Option<&Cptv2Header> {
+        self.decoder.header()
     }
 
     fn has_background_frame(&self) -> bool {
-        match &self.header_info {
-            CptvHeader::V2(h) => h.has_background_frame,
-            _ => false,
-        }
+        self.header().is_some_and(|header| header.has_background_frame)
     }
 
     #[wasm_bindgen(js_name = totalFrames)]
     pub fn get_total_frames(&self) -> JsValue {
         match self.total_frames() {
-            Some(total_frames) => match &self.header_info {
-                CptvHeader::V2(_) => JsValue::from_f64(total_frames as f64),
-                CptvHeader::V3(header) => JsValue::from_f64(header.num_frames as f64),
-                _ => JsValue::null(),
-            },
+            Some(total_frames) => JsValue::from_f64(total_frames as f64),
             None => JsValue::null(),
         }
     }
 
     #[wasm_bindgen(js_name = bytesLoaded)]
     pub fn get_bytes_loaded(&mut self) -> usize {
-        self.reader_mut().loaded_bytes
+        self.decoder.loaded_bytes()
     }
 
     #[wasm_bindgen(js_name = getNextFrame)]
     pub fn get_next_frame(&self) -> Uint16Array {
-        match &self.frame_buffer {
+        match self.decoder.frame() {
             Some(frame) => unsafe { Uint16Array::view(frame.image_data.data()) },
             None => Uint16Array::new_with_length(0),
         }
     }
 
+    /// Decodes the background frame, if the clip has one and it hasn't been read yet.  It's
+    /// the first frame in the stream, so this is cheap.  Doesn't affect which frame
+    /// `fetchNextFrame` returns next.
+    #[wasm_bindgen(js_name = fetchBackgroundFrame)]
+    pub async fn fetch_background_frame(
+        mut context: CptvPlayerContext,
+    ) -> Result<CptvPlayerContext, JsValue> {
+        if context.header().is_none() {
+            context = CptvPlayerContext::fetch_header(context).await?;
+        }
+        if context.has_background_frame()
+            && context.decoder.frame_count() == 0
+            && context.decoder.background().is_none()
+        {
+            // Decoding carries on past the background frame to the first frame of the clip,
+            // which is held on to for `fetchNextFrame`.
+            loop {
+                match context.decode_next(true).await?.map_err(stream_error)? {
+                    Decoded::Header => {}
+                    Decoded::Frame => {
+                        context.frame_pending = true;
+                        break;
+                    }
+                    _ => break,
+                }
+            }
+        }
+        Ok(context)
+    }
+
+    /// The background frame pixels, or null if there isn't one (or it hasn't been read yet).
+    #[wasm_bindgen(js_name = getBackgroundFrame)]
+    pub fn get_background_frame(&self) -> JsValue {
+        match self.decoder.background() {
+            Some(frame) => Uint16Array::from(frame.image_data.data()).into(),
+            None => JsValue::null(),
+        }
+    }
+
+    #[wasm_bindgen(js_name = getBackgroundFrameHeader)]
+    pub fn get_background_frame_header(&self) -> JsValue {
+        match self.decoder.background() {
+            Some(frame) => serde_wasm_bindgen::to_value(frame).unwrap(),
+            None => JsValue::null(),
+        }
+    }
+
     #[wasm_bindgen(js_name = getFrameHeader)]
     pub fn get_next_frame_header(&self) -> JsValue {
-        match &self.frame_buffer {
+        match self.decoder.frame() {
             Some(frame) => serde_wasm_bindgen::to_value(frame).unwrap(),
             None => JsValue::null(),
         }
     }
 
+    /// Enables motion detection on each frame as it's fetched.  `model` is either "stored", to
+    /// compare against the clip's background frame, or "runningAverage", with `alpha` setting
+    /// how quickly the background adapts.  `threshold` is in raw pixel units.  The clip median
+    /// model needs the whole clip up front, so isn't available while streaming.  Returns false
+    /// if `model` isn't recognised.
+    #[wasm_bindgen(js_name = setMotionDetection)]
+    pub fn set_motion_detection(
+        &mut self,
+        model: &str,
+        threshold: f32,
+        dilation: usize,
+        alpha: f32,
+    ) -> bool {
+        let model = match model {
+            "stored" => BackgroundModel::Stored,
+            "runningAverage" => BackgroundModel::RunningAverage { alpha },
+            _ => return false,
+        };
+        self.motion_settings = Some((model, MotionParams { threshold, dilation }));
+        self.motion_detector = None;
+        self.motion = None;
+        self.tracker = None;
+        true
+    }
+
+    #[wasm_bindgen(js_name = disableMotionDetection)]
+    pub fn disable_motion_detection(&mut self) {
+        self.motion_settings = None;
+        self.motion_detector = None;
+        self.motion = None;
+        self.tracker = None;
+    }
+
+    /// Gather statistics for `getClipStats` over the frames fetched from now on.  Off by
+    /// default; turning it off discards the statistics gathered so far.
+    #[wasm_bindgen(js_name = setCollectStats)]
+    pub fn set_collect_stats(&mut self, enabled: bool) {
+        self.collect_stats = enabled;
+        if !enabled {
+            self.clip_stats = None;
+        }
+    }
+
+    fn frame_fetched(&mut self) {
+        if let (true, Some(frame)) = (self.collect_stats, self.decoder.frame()) {
+            self.clip_stats
+                .get_or_insert_with(|| ClipStatsAccumulator::new(ClipStatsOptions::default()))
+                .add_frame(frame);
+        }
+        self.update_motion();
+    }
+
+    /// Statistics over the frames fetched so far: global and per-frame min/max, percentiles,
+    /// a histogram, and a recommended display range that leaves out outliers and frames
+    /// settling after an FFC.  Null if stats collection isn't enabled, or no frames have been
+    /// fetched.
+    #[wasm_bindgen(js_name = getClipStats)]
+    pub fn get_clip_stats(&self) -> JsValue {
+        match self.clip_stats.as_ref().and_then(|clip_stats| clip_stats.stats()) {
+            Some(stats) => serde_wasm_bindgen::to_value(&stats).unwrap(),
+            None => JsValue::null(),
+        }
+    }
+
+    fn update_motion(&mut self) {
+        let (model, params) = match self.motion_settings {
+            Some(settings) => settings,
+            None => return,
+        };
+        let frame = match self.decoder.frame() {
+            Some(frame) => frame,
+            None => return,
+        };
+        if self.motion_detector.is_none() {
+            self.motion_detector = match model {
+                BackgroundModel::Stored => self
+                    .decoder
+                    .background()
+                    .map(|background| MotionDetector::with_background(&background.image_data, params)),
+                BackgroundModel::RunningAverage { alpha } => Some(
+                    MotionDetector::with_running_average(&frame.image_data, alpha, params),
+                ),
+                BackgroundModel::Median => None,
+            };
+        }
+        self.motion = self
+            .motion_detector
+            .as_mut()
+            .map(|detector| detector.process(&frame.image_data));
+        if let Some(motion) = &self.motion {
+            let calibration = self.calibration();
+            self.tracker
+                .get_or_insert_with(|| Tracker::new(TrackerParams::default(), calibration))
+                .process(frame, &motion.mask);
+        }
+    }
+
+    /// Every track found by motion detection so far, or null if motion detection isn't enabled.
+    #[wasm_bindgen(js_name = getTracks)]
+    pub fn get_tracks(&self) -> JsValue {
+        match &self.tracker {
+            Some(tracker) => serde_wasm_bindgen::to_value(&tracker.tracks().to_vec()).unwrap(),
+            None => JsValue::null(),
+        }
+    }
+
+    /// Tracks present in the current frame, with their bounding boxes, or null if motion
+    /// detection isn't enabled.
+    #[wasm_bindgen(js_name = getFrameTracks)]
+    pub fn get_frame_tracks(&self) -> JsValue {
+        match &self.tracker {
+            Some(tracker) => {
+                let tracks: Vec<FrameTrack> = tracker
+                    .tracks_at(tracker.frame_count().saturating_sub(1))
+                    .collect();
+                serde_wasm_bindgen::to_value(&tracks).unwrap()
+            }
+            None => JsValue::null(),
+        }
+    }
+
+    /// Foreground mask for the current frame (1 for foreground, 0 for background), or null if
+    /// motion detection isn't enabled or there's no background to compare against.
+    #[wasm_bindgen(js_name = getMotionMask)]
+    pub fn get_motion_mask(&self) -> JsValue {
+        match &self.motion {
+            Some(motion) => Uint8Array::from(motion.mask.data()).into(),
+            None => JsValue::null(),
+        }
+    }
+
+    /// Fraction of the current frame that is foreground, or null if motion detection isn't
+    /// enabled or there's no background to compare against.
+    #[wasm_bindgen(js_name = getMotionScore)]
+    pub fn get_motion_score(&self) -> JsValue {
+        match &self.motion {
+            Some(motion) => JsValue::from_f64(motion.score as f64),
+            None => JsValue::null(),
+        }
+    }
+
+    /// The current frame converted to °C (approximately, for non-radiometric sensors), or null
+    /// if the sensor can't be calibrated.
+    #[wasm_bindgen(js_name = getFrameTemperatures)]
+    pub fn get_frame_temperatures(&self) -> JsValue {
+        match (self.calibration(), self.decoder.frame()) {
+            (Some(calibration), Some(frame)) => {
+                let temperatures = calibration.to_celsius(frame);
+                Float32Array::from(temperatures.data()).into()
+            }
+            _ => JsValue::null(),
+        }
+    }
+
+    /// Min, max and mean °C over a region of the current frame, or null if the sensor can't be
+    /// calibrated or the region lies outside the frame.
+    #[wasm_bindgen(js_name = getRegionTemperatureStats)]
+    pub fn get_region_temperature_stats(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> JsValue {
+        match (self.calibration(), self.decoder.frame()) {
+            (Some(calibration), Some(frame)) => {
+                match calibration.to_celsius(frame).region_stats(x, y, width, height) {
+                    Some(stats) => serde_wasm_bindgen::to_value(&stats).unwrap(),
+                    None => JsValue::null(),
+                }
+            }
+            _ => JsValue::null(),
+        }
+    }
+
+    fn calibration(&self) -> Option<Calibration> {
+        self.header().and_then(Calibration::for_header)
+    }
+
+    /// Wall-clock time of the current frame, in milliseconds since the unix epoch.
+    #[wasm_bindgen(js_name = getFrameTimestamp)]
+    pub fn get_frame_timestamp(&self) -> JsValue {
+        match (self.header(), self.decoder.frame(), self.times_on.first()) {
+            (Some(header), Some(frame), Some(start_time_on)) => {
+                let clock = ClipClock::with_start_time_on(header, *start_time_on);
+                JsValue::from_f64(clock.timestamp_micros(frame) as f64 / 1000.0)
+            }
+            _ => JsValue::null(),
+        }
+    }
+
+    /// Analysis of frame timing against the declared frame rate for the frames read so far,
+    /// reporting dropped frames, duplicated timestamps and jitter.
+    #[wasm_bindgen(js_name = getTimingReport)]
+    pub fn get_timing_report(&self) -> JsValue {
+        match self.header() {
+            Some(header) => {
+                let report = analyse_timing(self.times_on.iter().copied(), header.fps);
+                serde_wasm_bindgen::to_value(&report).unwrap()
+            }
+            None => JsValue::null(),
+        }
+    }
+
     #[wasm_bindgen(js_name = getWidth)]
     pub fn get_width(&self) -> u32 {
-        match &self.header_info {
-            CptvHeader::V2(h) => h.width,
-            CptvHeader::V3(h) => h.v2.width,
-            _ => panic!("uninitialised"),
+        match self.header() {
+            Some(h) => h.width,
+            None => panic!("uninitialised"),
         }
     }
 
     #[wasm_bindgen(js_name = getHeight)]
     pub fn get_height(&self) -> u32 {
-        match &self.header_info {
-            CptvHeader::V2(h) => h.height,
-            CptvHeader::V3(h) => h.v2.height,
-            _ => panic!("uninitialised"),
+        match self.header() {
+            Some(h) => h.height,
+            None => panic!("uninitialised"),
         }
     }
 
     #[wasm_bindgen(js_name = getFrameRate)]
     pub fn get_frame_rate(&self) -> u8 {
-        match &self.header_info {
-            CptvHeader::V2(h) => h.fps,
-            CptvHeader::V3(h) => h.v2.fps,
-            _ => panic!("uninitialised"),
+        match self.header() {
+            Some(h) => h.fps,
+            None => panic!("uninitialised"),
         }
     }
 
     #[wasm_bindgen(js_name = getFramesPerIframe)]
     pub fn get_frames_per_iframe(&self) -> u8 {
-        match &self.header_info {
-            CptvHeader::V2(_) => 1,
-            CptvHeader::V3(h) => h.frames_per_iframe,
-            _ => panic!("uninitialised"),
+        // Every v2 frame is delta encoded against the one before.
+        match self.header() {
+            Some(_) => 1,
+            None => panic!("uninitialised"),
         }
     }
 
-    async fn fetch_bytes(
-        mut context: CptvPlayerContext,
-    ) -> Result<(CptvPlayerContext, bool, usize), JsValue> {
-        let bytes_read = match context.pump_gz() {
-            Ok(r) => {
-                if r == 0 {
-                    if !context.reader().stream_ended {
-                        info!("Pump was dry, Asking for more bytes");
-                        context.get_bytes_from_stream(None).await?;
-                    } else {
-
-                        // Free memory used by reader
-                        context.reader_mut().inner.shrink_to_fit();
-                        context.downloaded_data.gz_ended = true;
-                    }
-                }
-                r
-            }
-            Err(e) => {
-                match e.kind() {
-                    ErrorKind::UnexpectedEof => {
-                        context.downloaded_data.gz_ended = true;
-                    }
-                    ErrorKind::WouldBlock => {}
-                    _ => {
-                        warn!("Gzip error {:?}", e);
-                    }
-                }
-                if !context.reader().stream_ended {
-                    // TODO(jon): Could get bytes a bit more greedily here to be able to read
-                    //  ahead and buffer a bit, esp on slow connections?
-                    info!("Asking for more bytes");
-                    context.get_bytes_from_stream(None).await?;
-                }
-                return Ok((context, true, 0));
-            }
-        };
-        context.downloaded_data.num_decompressed_bytes += bytes_read;
-        Ok((context, false, bytes_read))
-    }
-
     #[wasm_bindgen(js_name = fetchHeader)]
     pub async fn fetch_header(
         mut context: CptvPlayerContext,
     ) -> Result<CptvPlayerContext, JsValue> {
-        if context.gz_decoder.is_some() {
-            loop {
-                let (ctx, should_continue, _) = CptvPlayerContext::fetch_bytes(context).await?;
-                context = ctx;
-                if context.downloaded_data.parse_error {
-                    info!("Halt on parse error");
-                    break;
-                }
-                if should_continue {
-                    continue;
+        while context.header().is_none() {
+            match context.decode_next(true).await? {
+                Ok(Decoded::End) => {
+                    return Err(JsValue::from("Stream ends inside the CPTV header"));
                 }
-                assert_ne!(
-                    context.downloaded_data.gz_decoded.len(),
-                    0,
-                    "Should have some bytes from gzip decode"
-                );
-                let input = context.downloaded_data.gz_decoded.as_slices().0;
-                match decode_cptv_header(input) {
-                    Ok((remaining, header)) => {
-                        let remaining_count = remaining.len();
-                        while context.downloaded_data.gz_decoded.len() > remaining_count {
-                            // Pop off all of the header bytes
-                            context.downloaded_data.gz_decoded.pop_front();
-                        }
-                        context.header_info = header;
-                        break;
-                    }
-                    Err(e) => {
-                        match e {
-                            nom::Err::Incomplete(_) => {
-                                // Loop again and fetch more bytes.
-                                continue;
-                            }
-                            nom::Err::Error((_, _kind)) => {
-                                // We might have some kind of parsing error with the header?
-                                info!("{}", &format!("kind {:?}", _kind));
-                                break;
-                            }
-                            nom::Err::Failure((i, kind)) => {
-                                return Err(JsValue::from(format!(
-                                    "Fatal error parsing CPTV header: {:?}, {}",
-                                    kind, i[0] as char
-                                )));
-                            }
-                        }
-                    }
+                Ok(_) => {}
+                Err(e) => {
+                    // Leaves the header unset, so `getHeader` reports that it can't be parsed.
+                    info!("{}", e);
+                    break;
                 }
             }
-            Ok(context)
-        } else {
-            Err(JsValue::from("Stream not initialised"))
         }
+        Ok(context)
     }
 
     #[wasm_bindgen(js_name = getHeader)]
     pub fn get_header(&self) -> JsValue {
-        match &self.header_info {
-            CptvHeader::V2(h) => serde_wasm_bindgen::to_value(&h).unwrap(),
-            _ => JsValue::from_str("Unable to parse header"),
+        match self.header() {
+            Some(h) => serde_wasm_bindgen::to_value(h).unwrap(),
+            None => JsValue::from_str("Unable to parse header"),
         }
     }
 }
+
+fn stream_error(e: io::Error) -> JsValue {
+    warn!("{}", e);
+    JsValue::from(format!("Invalid or corrupted CPTV stream: {}", e))
+}
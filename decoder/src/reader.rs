@@ -0,0 +1,144 @@
+use crate::streaming::{Decoded, StreamingDecoder};
+use cptv_shared::v2::types::{Clip, Cptv2Header, CptvFrame, ModelExclusions};
+#[allow(unused)]
+use log::{info, trace, warn};
+use std::io;
+use std::io::{ErrorKind, Read};
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Synchronous counterpart to `CptvPlayerContext`, for decoding gzipped CPTV files natively.
+/// Feeds a `StreamingDecoder` from a blocking `Read`.
+///
+/// If the gzip stream is cut off partway through (interrupted uploads, cameras losing power),
+/// every complete frame before the cut is still returned, and the clip is flagged as truncated.
+///
+/// A background frame is not returned as one of the frames of the clip; use `background()`.
+/// Frame counts likewise exclude it.
+pub struct CptvReader<R: Read> {
+    inner: R,
+    decoder: StreamingDecoder,
+    read_buffer: Vec<u8>,
+
+    // Set when we decoded the first frame of the clip while looking for the background frame,
+    // so it still needs to be returned by `next_frame`.
+    frame_pending: bool,
+}
+
+impl<R: Read> CptvReader<R> {
+    pub fn new(inner: R) -> io::Result<CptvReader<R>> {
+        CptvReader::with_min_max_exclusions(inner, ModelExclusions::default())
+    }
+
+    /// Like `new`, but leaving the given pixels out of each frame's min and max, depending on
+    /// the camera model.
+    pub fn with_min_max_exclusions(
+        inner: R,
+        exclusions: ModelExclusions,
+    ) -> io::Result<CptvReader<R>> {
+        let mut reader = CptvReader {
+            inner,
+            decoder: StreamingDecoder::with_min_max_exclusions(exclusions),
+            read_buffer: vec![0; READ_CHUNK_SIZE],
+            frame_pending: false,
+        };
+        while reader.decoder.header().is_none() {
+            match reader.decoder.decode()? {
+                Decoded::NeedBytes => reader.fill()?,
+                Decoded::End => {
+                    return Err(io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "Stream ends inside the CPTV header",
+                    ))
+                }
+                _ => {}
+            }
+        }
+        if reader.header().has_background_frame {
+            // The background frame comes first, so make it available up front.
+            reader.frame_pending = reader.read_frame()?;
+        }
+        Ok(reader)
+    }
+
+    pub fn header(&self) -> &Cptv2Header {
+        self.decoder.header().unwrap()
+    }
+
+    /// Number of frames decoded so far, not counting any background frame.
+    pub fn frame_count(&self) -> usize {
+        self.decoder.frame_count()
+    }
+
+    /// If the stream was cut off, the offset into the decompressed stream at which the first
+    /// incomplete frame starts.  Only known once all the complete frames have been read.
+    pub fn truncated_at(&self) -> Option<usize> {
+        self.decoder.truncated_at()
+    }
+
+    /// The background frame, if the clip has one.
+    pub fn background(&self) -> Option<&CptvFrame> {
+        self.decoder.background()
+    }
+
+    /// Decodes the rest of the clip.
+    pub fn into_clip(mut self) -> io::Result<Clip> {
+        let frames = (&mut self).collect::<io::Result<Vec<_>>>()?;
+        Ok(Clip {
+            header: self.header().clone(),
+            background: self.decoder.background().cloned(),
+            frames,
+        })
+    }
+
+    /// Decodes the next frame, returning `None` once there are no more complete frames.
+    pub fn next_frame(&mut self) -> io::Result<Option<&CptvFrame>> {
+        if self.frame_pending {
+            self.frame_pending = false;
+            return Ok(self.decoder.frame());
+        }
+        match self.read_frame()? {
+            true => Ok(self.decoder.frame()),
+            false => Ok(None),
+        }
+    }
+
+    // Decodes up to and including the next frame of the clip, returning false at the end.
+    fn read_frame(&mut self) -> io::Result<bool> {
+        loop {
+            match self.decoder.decode()? {
+                Decoded::Header => {}
+                Decoded::Frame => return Ok(true),
+                Decoded::End => return Ok(false),
+                Decoded::NeedBytes => self.fill()?,
+            }
+        }
+    }
+
+    // Passes the next chunk read from the inner reader on to the decoder.
+    fn fill(&mut self) -> io::Result<()> {
+        if self.decoder.stream_ended() {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "Decoder wants more bytes after the end of the stream",
+            ));
+        }
+        loop {
+            match self.inner.read(&mut self.read_buffer) {
+                Ok(0) => self.decoder.end_stream(),
+                Ok(read_bytes) => self.decoder.push(&self.read_buffer[..read_bytes]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+            return Ok(());
+        }
+    }
+}
+
+impl<R: Read> Iterator for CptvReader<R> {
+    type Item = io::Result<CptvFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_frame().map(|frame| frame.cloned()).transpose()
+    }
+}
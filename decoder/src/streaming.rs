@@ -0,0 +1,703 @@
+use crate::decoder::decode_cptv_header;
+use cptv_shared::v2::types::{Cptv2Header, CptvFrame, ModelExclusions, SkippedRange};
+use cptv_shared::v2::{
+    decode_frame_header_v2, find_next_frame_header_v2, is_plausible_frame_header_v2,
+    parse_frame_header_v2, try_unpack_frame_v2,
+};
+use cptv_shared::CptvHeader;
+use libflate::non_blocking::gzip::Decoder;
+#[allow(unused)]
+use log::{info, trace, warn};
+use std::collections::VecDeque;
+use std::io;
+use std::io::{ErrorKind, Read};
+
+const GZ_BUFFER_SIZE: usize = 160 * 120 * 2;
+
+/// Compressed bytes received so far that the gzip decoder hasn't consumed yet.
+///
+/// Reads that can't be filled give `WouldBlock` until the stream has ended, so the non-blocking
+/// gzip decoder can pick up where it left off once more bytes are appended.
+pub struct ResumableReader {
+    pub(crate) inner: VecDeque<u8>,
+    loaded_bytes: usize,
+    pub(crate) stream_ended: bool,
+}
+
+impl ResumableReader {
+    pub fn new() -> ResumableReader {
+        ResumableReader {
+            inner: VecDeque::new(),
+            loaded_bytes: 0,
+            stream_ended: false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Total number of bytes appended so far.
+    pub fn loaded_bytes(&self) -> usize {
+        self.loaded_bytes
+    }
+
+    pub fn append(&mut self, bytes: &[u8]) {
+        self.inner.extend(bytes);
+        self.loaded_bytes += bytes.len();
+    }
+
+    /// Marks the end of the stream: no more bytes will be appended.
+    pub fn end_stream(&mut self) {
+        self.stream_ended = true;
+    }
+
+    pub fn stream_ended(&self) -> bool {
+        self.stream_ended
+    }
+}
+
+impl Default for ResumableReader {
+    fn default() -> Self {
+        ResumableReader::new()
+    }
+}
+
+impl Read for ResumableReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.len() < buf.len() && !self.stream_ended {
+            Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "Waiting for more bytes from stream",
+            ))
+        } else {
+            for (i, byte) in buf.iter_mut().enumerate() {
+                match self.inner.pop_front() {
+                    Some(next) => *byte = next,
+                    None => return Ok(i),
+                }
+            }
+            Ok(buf.len())
+        }
+    }
+}
+
+/// What happened on a call to `GzStream::pump`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pump {
+    /// This many bytes were decompressed.
+    Decoded(usize),
+
+    /// Nothing more can be decompressed until more bytes are appended.
+    NeedBytes,
+
+    /// The gzip stream has ended.  It was cut off partway through if `truncated` is set.
+    Ended { truncated: bool },
+}
+
+/// A gzip decoder that is fed compressed bytes as they arrive, rather than reading them itself,
+/// so it can sit behind any kind of stream: a JS `ReadableStream`, an `AsyncRead`, or chunks
+/// handed over by hand.
+pub struct GzStream {
+    decoder: Decoder<ResumableReader>,
+    buffer: Vec<u8>,
+}
+
+impl GzStream {
+    pub fn new(reader: ResumableReader) -> GzStream {
+        GzStream {
+            decoder: Decoder::new(reader),
+            buffer: vec![0; GZ_BUFFER_SIZE],
+        }
+    }
+
+    pub fn reader(&self) -> &ResumableReader {
+        self.decoder.as_inner_ref()
+    }
+
+    pub fn reader_mut(&mut self) -> &mut ResumableReader {
+        self.decoder.as_inner_mut()
+    }
+
+    /// Decompresses as much as the bytes appended so far allow, adding the result to `output`.
+    pub fn pump<E: Extend<u8>>(&mut self, output: &mut E) -> io::Result<Pump> {
+        match self.decoder.read(&mut self.buffer) {
+            Ok(0) => Ok(Pump::Ended { truncated: false }),
+            Ok(read_bytes) => {
+                output.extend(self.buffer[..read_bytes].iter().copied());
+                Ok(Pump::Decoded(read_bytes))
+            }
+            Err(e) => match e.kind() {
+                ErrorKind::WouldBlock | ErrorKind::Interrupted => Ok(Pump::NeedBytes),
+                ErrorKind::UnexpectedEof => {
+                    // The gzip stream was cut off, but we can still use everything before that.
+                    warn!("Gzip stream truncated");
+                    Ok(Pump::Ended { truncated: true })
+                }
+                _ => Err(e),
+            },
+        }
+    }
+}
+
+/// What `StreamingDecoder::decode` got to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decoded {
+    /// The clip header is available from `header()`.
+    Header,
+
+    /// The next frame of the clip is available from `frame()`.
+    Frame,
+
+    /// More bytes are needed before anything else can be decoded.
+    NeedBytes,
+
+    /// There are no more complete frames.
+    End,
+}
+
+/// Decodes gzipped CPTV v2 clips from bytes pushed in as they arrive, without doing any IO of
+/// its own.  This is the core of every decoder in this crate: `CptvReader` feeds it from a
+/// blocking `Read`, `AsyncCptvReader` from an `AsyncRead`, and `CptvPlayerContext` from a JS
+/// `ReadableStream`.
+///
+/// Push bytes with `push` and `end_stream`, then call `decode` until it asks for more.
+///
+/// A background frame isn't returned as a frame of the clip, but is available from
+/// `background()` once the first frame has been decoded, and a stream that is cut off still
+/// gives every complete frame before the cut.
+pub struct StreamingDecoder {
+    gz_stream: GzStream,
+
+    // Decompressed bytes not yet consumed.
+    buffer: Vec<u8>,
+    // Number of decompressed bytes consumed so far.
+    stream_offset: usize,
+    gz_ended: bool,
+    gz_truncated: bool,
+
+    header: Option<Cptv2Header>,
+    frame_buffer: Option<CptvFrame>,
+    frame_count: usize,
+    truncated_at: Option<usize>,
+    background: Option<CptvFrame>,
+    finished: bool,
+
+    /// When set, corrupted frames are skipped over rather than halting the decode.
+    recovery_mode: bool,
+    skipped_ranges: Vec<SkippedRange>,
+    /// Set when data was skipped, so the frame the next one is delta encoded against is lost,
+    /// until an intra frame gives an exact reference again.
+    reference_lost: bool,
+
+    exclusions: ModelExclusions,
+}
+
+impl StreamingDecoder {
+    pub fn new() -> StreamingDecoder {
+        StreamingDecoder::with_min_max_exclusions(ModelExclusions::default())
+    }
+
+    /// Like `new`, but leaving the given pixels out of each frame's min and max, depending on
+    /// the camera model.
+    pub fn with_min_max_exclusions(exclusions: ModelExclusions) -> StreamingDecoder {
+        StreamingDecoder {
+            gz_stream: GzStream::new(ResumableReader::new()),
+            buffer: Vec::new(),
+            stream_offset: 0,
+            gz_ended: false,
+            gz_truncated: false,
+            header: None,
+            frame_buffer: None,
+            frame_count: 0,
+            truncated_at: None,
+            background: None,
+            finished: false,
+            recovery_mode: false,
+            skipped_ranges: Vec::new(),
+            reference_lost: false,
+            exclusions,
+        }
+    }
+
+    /// Replaces the pixels left out of each frame's min and max, for the frames decoded from
+    /// now on.
+    pub fn set_min_max_exclusions(&mut self, exclusions: ModelExclusions) {
+        self.exclusions = exclusions;
+    }
+
+    /// Enables skipping over corrupted frames to the next frame that can be decoded.
+    ///
+    /// Frames after a skipped range are decoded against the last good frame rather than the one
+    /// that was lost, so each pixel is off by however much it changed over the lost frames: close
+    /// for a still scene, garbage for a busy one.  They have `decoded_after_skip` set.  Decoding
+    /// is exact again from the next intra frame, but files only have those after the first
+    /// frame where recordings were merged, so usually every frame after a skip is affected.
+    pub fn set_recovery_mode(&mut self, enabled: bool) {
+        self.recovery_mode = enabled;
+    }
+
+    /// Ranges of the decompressed stream skipped so far in recovery mode.
+    pub fn skipped_ranges(&self) -> &[SkippedRange] {
+        &self.skipped_ranges
+    }
+
+    /// Adds the next chunk of the gzipped stream.
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.gz_stream.reader_mut().append(bytes);
+    }
+
+    /// Marks the end of the gzipped stream.
+    pub fn end_stream(&mut self) {
+        self.gz_stream.reader_mut().end_stream();
+    }
+
+    pub fn stream_ended(&self) -> bool {
+        self.gz_stream.reader().stream_ended()
+    }
+
+    /// Total number of gzipped bytes pushed so far.
+    pub fn loaded_bytes(&self) -> usize {
+        self.gz_stream.reader().loaded_bytes()
+    }
+
+    /// Gzipped bytes pushed but not yet decompressed.
+    pub(crate) fn pending_input(&self) -> &ResumableReader {
+        self.gz_stream.reader()
+    }
+
+    /// The clip header, once it has been decoded.
+    pub fn header(&self) -> Option<&Cptv2Header> {
+        self.header.as_ref()
+    }
+
+    /// The frame most recently returned by `decode`.
+    pub fn frame(&self) -> Option<&CptvFrame> {
+        self.frame_buffer.as_ref()
+    }
+
+    /// The background frame, if the clip has one and it has been decoded.
+    pub fn background(&self) -> Option<&CptvFrame> {
+        self.background.as_ref()
+    }
+
+    /// Number of frames decoded so far, not counting any background frame.
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    /// True once `decode` has returned `End`, at which point `frame_count` is the total.
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    /// If the stream was cut off, the offset into the decompressed stream at which the first
+    /// incomplete frame starts.  Only known once all the complete frames have been decoded.
+    pub fn truncated_at(&self) -> Option<usize> {
+        self.truncated_at
+    }
+
+    /// Decodes the header, or the next frame, from the bytes pushed so far.
+    pub fn decode(&mut self) -> io::Result<Decoded> {
+        self.decode_next(true)
+    }
+
+    /// Like `decode`, but only reads the next frame's header, leaving its pixels blank.  This
+    /// is much quicker for counting frames, but as frames are delta encoded, any frame
+    /// `decode`d after a skipped one is garbage.
+    pub fn skip_frame(&mut self) -> io::Result<Decoded> {
+        self.decode_next(false)
+    }
+
+    fn decode_next(&mut self, unpack: bool) -> io::Result<Decoded> {
+        loop {
+            if self.finished {
+                return Ok(Decoded::End);
+            }
+            let header = match &self.header {
+                Some(header) => header,
+                None => match decode_cptv_header(&self.buffer) {
+                    Ok((remaining, CptvHeader::V2(header))) => {
+                        let header_length = self.buffer.len() - remaining.len();
+                        self.consume(header_length);
+                        self.header = Some(header);
+                        return Ok(Decoded::Header);
+                    }
+                    Ok(_) => {
+                        return Err(io::Error::new(
+                            ErrorKind::Unsupported,
+                            "Only CPTV v2 files are supported",
+                        ))
+                    }
+                    Err(nom::Err::Incomplete(_)) => match self.fill()? {
+                        Pump::Decoded(_) => continue,
+                        Pump::NeedBytes => return Ok(Decoded::NeedBytes),
+                        Pump::Ended { .. } => {
+                            return Err(io::Error::new(
+                                ErrorKind::UnexpectedEof,
+                                "Stream ends inside the CPTV header",
+                            ))
+                        }
+                    },
+                    Err(nom::Err::Error((_, kind))) | Err(nom::Err::Failure((_, kind))) => {
+                        return Err(io::Error::new(
+                            ErrorKind::InvalidData,
+                            format!("Unable to parse CPTV header: {:?}", kind),
+                        ))
+                    }
+                },
+            };
+            let width = header.width as usize;
+            let height = header.height as usize;
+            // In recovery mode, a frame header that parses but doesn't look like a real frame
+            // means we're looking at corrupted data.
+            let implausible = self.recovery_mode
+                && parse_frame_header_v2(&self.buffer, width, height).is_ok()
+                && !is_plausible_frame_header_v2(&self.buffer, width, height);
+            match decode_frame_header_v2(&self.buffer, width, height, false) {
+                Ok((remaining, (frame_data, mut frame))) if !implausible => {
+                    let frame_length = self.buffer.len() - remaining.len();
+                    if unpack {
+                        if !try_unpack_frame_v2(&self.frame_buffer, frame_data, &mut frame) {
+                            if self.recovery_mode {
+                                // The frame header was fine but its data is corrupt, so skip
+                                // the whole frame and carry on from the next one.
+                                self.skip_bytes(frame_length);
+                                continue;
+                            }
+                            return Err(io::Error::new(
+                                ErrorKind::InvalidData,
+                                format!("Corrupt frame data in frame #{}", self.frame_count),
+                            ));
+                        }
+                        if frame.is_intra_frame {
+                            self.reference_lost = false;
+                        }
+                        frame.decoded_after_skip = self.reference_lost;
+                        frame
+                            .image_data
+                            .exclude_from_range(self.exclusions.for_header(header));
+                    }
+                    self.consume(frame_length);
+                    if frame.is_background_frame {
+                        if unpack {
+                            // Later frames are still delta encoded against the background frame.
+                            self.background = Some(frame.clone());
+                            self.frame_buffer = Some(frame);
+                        }
+                        continue;
+                    }
+                    self.frame_count += 1;
+                    self.frame_buffer = Some(frame);
+                    return Ok(Decoded::Frame);
+                }
+                Err(nom::Err::Incomplete(_)) if !implausible => match self.fill()? {
+                    Pump::Decoded(_) => {}
+                    Pump::NeedBytes => return Ok(Decoded::NeedBytes),
+                    Pump::Ended { truncated } => {
+                        if !self.buffer.is_empty() || truncated {
+                            self.mark_truncated();
+                        }
+                        self.finished = true;
+                    }
+                },
+                Err(nom::Err::Error((_, kind))) | Err(nom::Err::Failure((_, kind)))
+                    if !self.recovery_mode =>
+                {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "Unable to parse header of frame #{} at offset {}: {:?}",
+                            self.frame_count, self.stream_offset, kind
+                        ),
+                    ));
+                }
+                _ => {
+                    // We're in recovery mode and looking at something that isn't a valid
+                    // frame, so scan forward for the next frame header we can use.
+                    if !self.resync(width, height) {
+                        match self.fill()? {
+                            Pump::Decoded(_) => {}
+                            Pump::NeedBytes => return Ok(Decoded::NeedBytes),
+                            Pump::Ended { .. } => {
+                                self.skip_bytes(self.buffer.len());
+                                self.finished = true;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn mark_truncated(&mut self) {
+        warn!(
+            "Stream truncated at offset {} after {} frames",
+            self.stream_offset, self.frame_count
+        );
+        self.truncated_at = Some(self.stream_offset);
+        self.buffer.clear();
+        // The header should describe what we actually recovered.
+        if let Some(header) = &mut self.header {
+            header.total_frame_count = Some(self.frame_count as u16);
+        }
+    }
+
+    /// Skips forward to the next plausible frame header in the buffer, if there is one.
+    fn resync(&mut self, width: usize, height: usize) -> bool {
+        if self.buffer.is_empty() {
+            return false;
+        }
+        match find_next_frame_header_v2(&self.buffer[1..], width, height) {
+            Some(offset) => {
+                warn!("Skipping {} bytes of corrupted data", offset + 1);
+                self.skip_bytes(offset + 1);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Consumes `count` bytes, recording them as skipped.
+    fn skip_bytes(&mut self, count: usize) {
+        if count == 0 {
+            return;
+        }
+        let offset = self.stream_offset;
+        match self.skipped_ranges.last_mut() {
+            Some(range) if range.offset + range.length == offset => range.length += count,
+            _ => self.skipped_ranges.push(SkippedRange {
+                offset,
+                length: count,
+                frame_index: self.frame_count,
+            }),
+        }
+        self.reference_lost = true;
+        self.consume(count);
+    }
+
+    fn consume(&mut self, count: usize) {
+        self.buffer.drain(..count);
+        self.stream_offset += count;
+    }
+
+    fn fill(&mut self) -> io::Result<Pump> {
+        if self.gz_ended {
+            return Ok(Pump::Ended {
+                truncated: self.gz_truncated,
+            });
+        }
+        let pumped = self.gz_stream.pump(&mut self.buffer)?;
+        if let Pump::Ended { truncated } = pumped {
+            self.gz_ended = true;
+            self.gz_truncated = truncated;
+        }
+        Ok(pumped)
+    }
+}
+
+impl Default for StreamingDecoder {
+    fn default() -> Self {
+        StreamingDecoder::new()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::reader::CptvReader;
+    use cptv_encoder::encode_clip;
+    use cptv_shared::v2::types::{Clip, FrameData};
+    use std::io::Write;
+
+    const WIDTH: usize = 16;
+    const HEIGHT: usize = 12;
+
+    /// A gzipped clip with a background frame and `frames` frames of changing pixels.
+    pub(crate) fn test_cptv_file(frames: usize) -> Vec<u8> {
+        encode_clip(&test_clip(frames))
+    }
+
+    fn test_clip(frames: usize) -> Clip {
+        let mut header = Cptv2Header::new();
+        header.width = WIDTH as u32;
+        header.height = HEIGHT as u32;
+        header.has_background_frame = true;
+        let frame = |index: usize, is_background_frame: bool| {
+            let pixels: Vec<u16> = (0..WIDTH * HEIGHT)
+                .map(|pixel| (1000 + (pixel * 7 + index * 31) % 500) as u16)
+                .collect();
+            let mut frame = CptvFrame::new_with_dimensions(WIDTH, HEIGHT);
+            frame.image_data = FrameData::with_dimensions_and_data(WIDTH, HEIGHT, &pixels);
+            frame.time_on = 10_000 + 111 * index as u32;
+            frame.is_background_frame = is_background_frame;
+            frame
+        };
+        Clip {
+            header,
+            background: Some(frame(0, true)),
+            frames: (1..=frames).map(|index| frame(index, false)).collect(),
+        }
+    }
+
+    // Where each frame starts in the decompressed stream, including the background frame.
+    fn frame_offsets(stream: &[u8]) -> Vec<usize> {
+        let (mut remaining, _) = decode_cptv_header(stream).unwrap();
+        let mut offsets = Vec::new();
+        while let Ok((next, _)) = decode_frame_header_v2(remaining, WIDTH, HEIGHT, false) {
+            offsets.push(stream.len() - remaining.len());
+            remaining = next;
+        }
+        offsets
+    }
+
+    fn gunzip(bytes: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        libflate::gzip::Decoder::new(bytes)
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        output
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = libflate::gzip::Encoder::new(Vec::new()).unwrap();
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().into_result().unwrap()
+    }
+
+    // Every frame decoded from `bytes`, pushed in chunks of `chunk_size`.
+    fn decode_in_chunks(
+        decoder: &mut StreamingDecoder,
+        bytes: &[u8],
+        chunk_size: usize,
+    ) -> io::Result<Vec<CptvFrame>> {
+        let mut chunks = bytes.chunks(chunk_size);
+        let mut frames = Vec::new();
+        loop {
+            match decoder.decode()? {
+                Decoded::Header => {}
+                Decoded::Frame => frames.push(decoder.frame().unwrap().clone()),
+                Decoded::NeedBytes => match chunks.next() {
+                    Some(chunk) => decoder.push(chunk),
+                    None => decoder.end_stream(),
+                },
+                Decoded::End => return Ok(frames),
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_the_same_frames_in_any_chunk_size() {
+        let file = test_cptv_file(5);
+        let expected: Vec<_> = CptvReader::new(&file[..])
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(expected.len(), 5);
+        for chunk_size in [1, 7, 100, file.len()] {
+            let mut decoder = StreamingDecoder::new();
+            let frames = decode_in_chunks(&mut decoder, &file, chunk_size).unwrap();
+            assert_eq!(frames.len(), expected.len());
+            for (frame, expected) in frames.iter().zip(&expected) {
+                assert_eq!(frame.image_data.data(), expected.image_data.data());
+                assert_eq!(frame.time_on, expected.time_on);
+            }
+            assert!(decoder.background().is_some());
+            assert!(decoder.finished());
+            assert_eq!(decoder.truncated_at(), None);
+        }
+    }
+
+    #[test]
+    fn recovery_mode_skips_corrupt_frames_and_flags_the_rest() {
+        let mut stream = gunzip(&test_cptv_file(5));
+        let offsets = frame_offsets(&stream);
+        assert_eq!(offsets.len(), 6);
+        // Break the header of the third frame of the clip.
+        stream[offsets[3]] = b'X';
+        let corrupt = gzip(&stream);
+
+        assert!(decode_in_chunks(&mut StreamingDecoder::new(), &corrupt, 64).is_err());
+
+        let mut decoder = StreamingDecoder::new();
+        decoder.set_recovery_mode(true);
+        let frames = decode_in_chunks(&mut decoder, &corrupt, 64).unwrap();
+        assert_eq!(frames.len(), 4);
+        let skipped = decoder.skipped_ranges();
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].offset, offsets[3]);
+        assert_eq!(skipped[0].length, offsets[4] - offsets[3]);
+        assert_eq!(skipped[0].frame_index, 2);
+        let after_skip: Vec<_> = frames
+            .iter()
+            .map(|frame| frame.decoded_after_skip)
+            .collect();
+        assert_eq!(after_skip, [false, false, true, true]);
+    }
+
+    #[test]
+    fn pixels_after_a_skip_are_exact_again_from_the_next_intra_frame() {
+        let mut clip = test_clip(6);
+        clip.frames[3].is_intra_frame = true;
+        let mut stream = gunzip(&encode_clip(&clip));
+        let offsets = frame_offsets(&stream);
+        // Break the header of the second frame of the clip.
+        stream[offsets[2]] = b'X';
+
+        let mut decoder = StreamingDecoder::new();
+        decoder.set_recovery_mode(true);
+        let frames = decode_in_chunks(&mut decoder, &gzip(&stream), 64).unwrap();
+        let expected: Vec<&CptvFrame> = clip
+            .frames
+            .iter()
+            .filter(|frame| frame.time_on != clip.frames[1].time_on)
+            .collect();
+        assert_eq!(frames.len(), expected.len());
+        let after_skip: Vec<_> = frames
+            .iter()
+            .map(|frame| frame.decoded_after_skip)
+            .collect();
+        assert_eq!(after_skip, [false, true, false, false, false]);
+
+        // The frame after the skip was decoded against the first frame rather than the lost
+        // second one, so it's off by the change between them.
+        let pixels = |frame: &CptvFrame| frame.image_data.data().to_vec();
+        let off_by_lost_change: Vec<u16> = pixels(&clip.frames[2])
+            .iter()
+            .zip(pixels(&clip.frames[1]))
+            .zip(pixels(&clip.frames[0]))
+            .map(|((current, lost), reference)| {
+                (*current as i32 - lost as i32 + reference as i32) as u16
+            })
+            .collect();
+        assert_ne!(pixels(&frames[1]), pixels(&clip.frames[2]));
+        assert_eq!(pixels(&frames[1]), off_by_lost_change);
+
+        // From the intra frame on, the pixels are exact again.
+        assert_eq!(pixels(&frames[0]), pixels(expected[0]));
+        for (frame, expected) in frames.iter().zip(&expected).skip(2) {
+            assert_eq!(
+                pixels(frame),
+                pixels(expected),
+                "frame at {}",
+                frame.time_on
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_the_frames_before_a_cut() {
+        let stream = gunzip(&test_cptv_file(5));
+        let cut = gzip(&stream[..stream.len() - 10]);
+        let mut decoder = StreamingDecoder::new();
+        let frames = decode_in_chunks(&mut decoder, &cut, 100).unwrap();
+        assert_eq!(frames.len(), 4);
+        assert!(decoder.truncated_at().is_some());
+        assert_eq!(decoder.header().unwrap().total_frame_count, Some(4));
+    }
+}
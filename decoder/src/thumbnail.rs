@@ -0,0 +1,182 @@
+use crate::reader::CptvReader;
+use cptv_shared::v2::colour_map::ColourMap;
+use cptv_shared::v2::motion::{analyse_motion, BackgroundModel, MotionFrame, MotionParams};
+use cptv_shared::v2::tracking::{find_regions, BoundingBox};
+use cptv_shared::v2::types::{CptvFrame, FrameData};
+use std::io;
+use std::io::{ErrorKind, Read};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameSelection {
+    /// The frame with the most foreground activity, compared against the background frame if
+    /// there is one, or the clip median otherwise.  Clips with no activity fall back to
+    /// `PeakTemperature`.
+    MostActivity,
+
+    /// The frame containing the hottest pixel.
+    PeakTemperature,
+}
+
+#[derive(Debug, Clone)]
+pub struct ThumbnailOptions {
+    pub selection: FrameSelection,
+
+    /// Crop to a window of this width and height around the hottest region, rather than
+    /// showing the whole frame.
+    pub crop: Option<(usize, usize)>,
+
+    pub colour_map: ColourMap,
+
+    /// Integer factor to scale the image up by.
+    pub scale: usize,
+
+    pub motion_params: MotionParams,
+}
+
+impl Default for ThumbnailOptions {
+    fn default() -> Self {
+        ThumbnailOptions {
+            selection: FrameSelection::MostActivity,
+            crop: None,
+            colour_map: ColourMap::Ironbow,
+            scale: 1,
+            motion_params: MotionParams::default(),
+        }
+    }
+}
+
+pub struct Thumbnail {
+    pub png: Vec<u8>,
+
+    /// Index of the chosen frame, not counting any background frame.
+    pub frame_index: usize,
+
+    /// Part of the frame shown in the thumbnail.
+    pub bounds: BoundingBox,
+}
+
+/// Reads the rest of the clip from `reader`, picks a representative frame and renders it to a PNG.
+pub fn thumbnail<R: Read>(
+    reader: &mut CptvReader<R>,
+    options: &ThumbnailOptions,
+) -> io::Result<Thumbnail> {
+    let frames = reader.collect::<Result<Vec<_>, _>>()?;
+    if frames.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidData, "Clip has no frames"));
+    }
+    let background = reader.background();
+
+    let motion = match options.selection {
+        FrameSelection::MostActivity => {
+            let model = match background {
+                Some(_) => BackgroundModel::Stored,
+                None => BackgroundModel::Median,
+            };
+            analyse_motion(background.into_iter().chain(frames.iter()), model, options.motion_params)
+        }
+        FrameSelection::PeakTemperature => None,
+    };
+    let most_active = motion.as_ref().and_then(|motion| {
+        motion
+            .iter()
+            .enumerate()
+            .filter(|(_, motion)| motion.score > 0.0)
+            .max_by(|(_, a), (_, b)| a.score.total_cmp(&b.score))
+            .map(|(frame_index, _)| frame_index)
+    });
+    let frame_index = most_active.unwrap_or_else(|| {
+        frames
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, frame)| frame.image_data.data().iter().max().copied())
+            .map(|(frame_index, _)| frame_index)
+            .unwrap()
+    });
+    let frame = &frames[frame_index];
+
+    let bounds = match options.crop {
+        Some((width, height)) => {
+            let frame_motion = most_active.and(motion.as_ref()).map(|motion| &motion[frame_index]);
+            let (x, y) = hottest_point(frame, frame_motion);
+            crop_bounds(&frame.image_data, x, y, width, height)
+        }
+        None => BoundingBox {
+            x: 0,
+            y: 0,
+            width: frame.image_data.width(),
+            height: frame.image_data.height(),
+        },
+    };
+    let png = render_png(&frame.image_data, bounds, options.colour_map, options.scale.max(1))?;
+    Ok(Thumbnail {
+        png,
+        frame_index,
+        bounds,
+    })
+}
+
+// The hottest pixel of the hottest moving region, or failing that the hottest pixel in the
+// frame.  Preferring moving regions keeps the crop off static hot spots like a warm rock.
+fn hottest_point(frame: &CptvFrame, motion: Option<&MotionFrame>) -> (usize, usize) {
+    if let Some(motion) = motion {
+        let hottest = find_regions(frame, &motion.mask, None, 1)
+            .into_iter()
+            .max_by_key(|region| region.peak_value);
+        if let Some(region) = hottest {
+            return region.peak_position;
+        }
+    }
+    let width = frame.image_data.width();
+    let (index, _) = frame
+        .image_data
+        .data()
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, value)| **value)
+        .unwrap_or((0, &0));
+    (index % width, index / width)
+}
+
+// A window centred on `x`, `y` as far as possible while staying inside the frame.
+fn crop_bounds(frame: &FrameData, x: usize, y: usize, width: usize, height: usize) -> BoundingBox {
+    let width = width.clamp(1, frame.width());
+    let height = height.clamp(1, frame.height());
+    BoundingBox {
+        x: x.saturating_sub(width / 2).min(frame.width() - width),
+        y: y.saturating_sub(height / 2).min(frame.height() - height),
+        width,
+        height,
+    }
+}
+
+fn render_png(
+    frame: &FrameData,
+    bounds: BoundingBox,
+    colour_map: ColourMap,
+    scale: usize,
+) -> io::Result<Vec<u8>> {
+    let mut pixels = Vec::with_capacity(bounds.width * bounds.height * scale * scale);
+    for y in bounds.y..bounds.y + bounds.height {
+        let row = &frame.data()[y * frame.width() + bounds.x..][..bounds.width];
+        for _ in 0..scale {
+            for value in row {
+                pixels.extend(std::iter::repeat_n(*value, scale));
+            }
+        }
+    }
+    let (width, height) = (bounds.width * scale, bounds.height * scale);
+    let cropped = FrameData::with_dimensions_and_data(width, height, &pixels);
+    // Normalise to what's actually shown, so a crop gets the full range of the colour map.
+    let min = pixels.iter().min().copied().unwrap_or(0);
+    let max = pixels.iter().max().copied().unwrap_or(0);
+    let rgb = colour_map.render(&cropped, min, max);
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&rgb).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)?;
+    Ok(png)
+}
@@ -0,0 +1,411 @@
+use crate::decoder::decode_cptv_header;
+use cptv_shared::v2::{decode_frame_header_v2, expected_frame_size, MAX_BIT_WIDTH};
+use cptv_shared::v2::timing::analyse_timing;
+use cptv_shared::v2::types::Cptv2Header;
+use cptv_shared::CptvHeader;
+use libflate::gzip::Decoder;
+use serde::Serialize;
+use std::io::{ErrorKind, Read};
+
+/// Largest width or height we consider plausible for a thermal sensor.
+pub const MAX_DIMENSION: u32 = 2048;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum IssueKind {
+    NotGzipped,
+    TruncatedGzip,
+    CorruptGzip,
+    BadMagic,
+    UnsupportedVersion,
+    InvalidHeader,
+    BadDimensions,
+    CorruptFrameHeader,
+    FrameSizeMismatch,
+    TruncatedFrame,
+    TrailingData,
+    MissingBackgroundFrame,
+    UnexpectedBackgroundFrame,
+    FrameCountMismatch,
+    TimingMismatch,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ValidationIssue {
+    pub severity: Severity,
+    pub kind: IssueKind,
+
+    // Index of the offending frame, counting every frame in the stream including
+    // any background frame.
+    #[serde(rename = "frameIndex")]
+    pub frame_index: Option<usize>,
+
+    // Byte offset into the decompressed stream, or into the gzipped file for gzip level issues.
+    pub offset: Option<usize>,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ValidationReport {
+    #[serde(rename = "compressedBytes")]
+    pub compressed_bytes: usize,
+    #[serde(rename = "decompressedBytes")]
+    pub decompressed_bytes: usize,
+
+    /// Number of frames found, excluding any background frame.
+    #[serde(rename = "frameCount")]
+    pub frame_count: usize,
+    #[serde(rename = "hasBackgroundFrame")]
+    pub has_background_frame: bool,
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// True if no errors were found.  Warnings don't make a file invalid.
+    pub fn is_valid(&self) -> bool {
+        !self.issues.iter().any(|issue| issue.severity == Severity::Error)
+    }
+
+    pub fn has_warnings(&self) -> bool {
+        self.issues
+            .iter()
+            .any(|issue| issue.severity == Severity::Warning)
+    }
+
+    fn push(
+        &mut self,
+        severity: Severity,
+        kind: IssueKind,
+        frame_index: Option<usize>,
+        offset: Option<usize>,
+        message: String,
+    ) {
+        self.issues.push(ValidationIssue {
+            severity,
+            kind,
+            frame_index,
+            offset,
+            message,
+        });
+    }
+}
+
+/// Checks a gzipped CPTV file for conformance, and reports every problem found rather than
+/// stopping at the first one.
+pub fn validate(bytes: &[u8]) -> ValidationReport {
+    let mut report = ValidationReport {
+        compressed_bytes: bytes.len(),
+        ..Default::default()
+    };
+    if let Some(decompressed) = gunzip(bytes, &mut report) {
+        report.decompressed_bytes = decompressed.len();
+        validate_stream(&decompressed, &mut report);
+    }
+    report
+}
+
+fn gunzip(bytes: &[u8], report: &mut ValidationReport) -> Option<Vec<u8>> {
+    if !(bytes.len() >= 2 && bytes[0] == 0x1f && bytes[1] == 0x8b) {
+        report.push(
+            Severity::Error,
+            IssueKind::NotGzipped,
+            None,
+            Some(0),
+            "File does not start with a gzip header".to_string(),
+        );
+        return None;
+    }
+    let mut decoder = match Decoder::new(bytes) {
+        Ok(decoder) => decoder,
+        Err(e) => {
+            report.push(
+                Severity::Error,
+                IssueKind::CorruptGzip,
+                None,
+                Some(0),
+                format!("Invalid gzip header: {}", e),
+            );
+            return None;
+        }
+    };
+    let mut output = Vec::new();
+    let mut buffer = [0u8; 8192];
+    loop {
+        match decoder.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read_bytes) => output.extend_from_slice(&buffer[..read_bytes]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => {
+                output.extend_from_slice(decoder.unread_decoded_data());
+                let consumed = bytes.len() - decoder.as_inner_ref().len();
+                let kind = if e.kind() == ErrorKind::UnexpectedEof {
+                    IssueKind::TruncatedGzip
+                } else {
+                    IssueKind::CorruptGzip
+                };
+                report.push(
+                    Severity::Error,
+                    kind,
+                    None,
+                    Some(consumed),
+                    format!(
+                        "Gzip stream ended early after {} decompressed bytes: {}",
+                        output.len(),
+                        e
+                    ),
+                );
+                return Some(output);
+            }
+        }
+    }
+    let trailing = decoder.into_inner().len();
+    if trailing != 0 {
+        report.push(
+            Severity::Error,
+            IssueKind::TrailingData,
+            None,
+            Some(bytes.len() - trailing),
+            format!("{} bytes of trailing data after end of gzip stream", trailing),
+        );
+    }
+    Some(output)
+}
+
+fn validate_stream(input: &[u8], report: &mut ValidationReport) {
+    if input.len() < 4 || &input[0..4] != b"CPTV" {
+        report.push(
+            Severity::Error,
+            IssueKind::BadMagic,
+            None,
+            Some(0),
+            "Stream does not start with 'CPTV'".to_string(),
+        );
+        return;
+    }
+    match input.get(4) {
+        Some(1) | Some(2) => {}
+        Some(version) => {
+            report.push(
+                Severity::Error,
+                IssueKind::UnsupportedVersion,
+                None,
+                Some(4),
+                format!("Unsupported CPTV version {}", version),
+            );
+            return;
+        }
+        None => {}
+    }
+    let (mut rest, header) = match decode_cptv_header(input) {
+        Ok((rest, CptvHeader::V2(header))) => (rest, header),
+        Ok(_) => return,
+        Err(e) => {
+            let message = match e {
+                nom::Err::Incomplete(_) => "Stream ends inside the file header".to_string(),
+                nom::Err::Error((_, kind)) | nom::Err::Failure((_, kind)) => {
+                    format!("Unable to parse file header: {:?}", kind)
+                }
+            };
+            report.push(
+                Severity::Error,
+                IssueKind::InvalidHeader,
+                None,
+                Some(5),
+                message,
+            );
+            return;
+        }
+    };
+    if !validate_header(&header, report) {
+        return;
+    }
+
+    let width = header.width as usize;
+    let height = header.height as usize;
+    let mut frame_index = 0;
+    let mut times_on = Vec::new();
+    while !rest.is_empty() {
+        let offset = input.len() - rest.len();
+        match decode_frame_header_v2(rest, width, height, false) {
+            Ok((remaining, (_, frame))) => {
+                if frame.bit_width == 0 || frame.bit_width > MAX_BIT_WIDTH {
+                    report.push(
+                        Severity::Error,
+                        IssueKind::CorruptFrameHeader,
+                        Some(frame_index),
+                        Some(offset),
+                        format!("Invalid bit width {}", frame.bit_width),
+                    );
+                } else {
+                    let expected = expected_frame_size(frame.bit_width, width, height);
+                    if frame.frame_size as usize != expected {
+                        report.push(
+                            Severity::Error,
+                            IssueKind::FrameSizeMismatch,
+                            Some(frame_index),
+                            Some(offset),
+                            format!(
+                                "Frame size is {} bytes, but {}x{} at {} bits per pixel needs {}",
+                                frame.frame_size, width, height, frame.bit_width, expected
+                            ),
+                        );
+                    }
+                }
+                if frame.is_background_frame {
+                    if report.has_background_frame || !header.has_background_frame {
+                        report.push(
+                            Severity::Warning,
+                            IssueKind::UnexpectedBackgroundFrame,
+                            Some(frame_index),
+                            Some(offset),
+                            "Background frame not declared in the file header".to_string(),
+                        );
+                    }
+                    report.has_background_frame = true;
+                } else {
+                    times_on.push((frame_index, frame.time_on));
+                }
+                frame_index += 1;
+                rest = remaining;
+            }
+            Err(nom::Err::Incomplete(_)) => {
+                report.push(
+                    Severity::Error,
+                    IssueKind::TruncatedFrame,
+                    Some(frame_index),
+                    Some(offset),
+                    format!("Stream ends partway through frame, {} bytes remain", rest.len()),
+                );
+                break;
+            }
+            Err(_) => {
+                if rest[0] == b'F' {
+                    report.push(
+                        Severity::Error,
+                        IssueKind::CorruptFrameHeader,
+                        Some(frame_index),
+                        Some(offset),
+                        "Unable to parse frame header".to_string(),
+                    );
+                } else {
+                    report.push(
+                        Severity::Error,
+                        IssueKind::TrailingData,
+                        None,
+                        Some(offset),
+                        format!("{} bytes of trailing data after last frame", rest.len()),
+                    );
+                }
+                break;
+            }
+        }
+    }
+    report.frame_count = times_on.len();
+
+    if header.has_background_frame && !report.has_background_frame {
+        report.push(
+            Severity::Error,
+            IssueKind::MissingBackgroundFrame,
+            None,
+            None,
+            "Header declares a background frame, but none was found".to_string(),
+        );
+    }
+    if let Some(total_frame_count) = header.total_frame_count {
+        if total_frame_count as usize != report.frame_count {
+            report.push(
+                Severity::Error,
+                IssueKind::FrameCountMismatch,
+                None,
+                None,
+                format!(
+                    "Header declares {} frames, but {} were found",
+                    total_frame_count, report.frame_count
+                ),
+            );
+        }
+    }
+    validate_timing(&times_on, header.fps, report);
+}
+
+fn validate_header(header: &Cptv2Header, report: &mut ValidationReport) -> bool {
+    let mut valid = true;
+    for (name, value) in [("width", header.width), ("height", header.height)] {
+        if value == 0 || value > MAX_DIMENSION {
+            report.push(
+                Severity::Error,
+                IssueKind::BadDimensions,
+                None,
+                None,
+                format!("Implausible {} {}", name, value),
+            );
+            valid = false;
+        }
+    }
+    if header.fps == 0 {
+        report.push(
+            Severity::Error,
+            IssueKind::InvalidHeader,
+            None,
+            None,
+            "Header declares a frame rate of 0".to_string(),
+        );
+    }
+    valid
+}
+
+fn validate_timing(times_on: &[(usize, u32)], fps: u8, report: &mut ValidationReport) {
+    if fps == 0 {
+        return;
+    }
+    let timing = analyse_timing(times_on.iter().map(|(_, time_on)| *time_on), fps);
+    // Timing reports index frames excluding the background frame, so map back to stream indices.
+    let frame_index = |index: Option<&usize>| index.map(|index| times_on[*index].0);
+    let non_increasing = timing.duplicated_timestamps.len() + timing.out_of_order.len();
+    if non_increasing != 0 {
+        let first = timing
+            .duplicated_timestamps
+            .first()
+            .into_iter()
+            .chain(timing.out_of_order.first())
+            .min();
+        report.push(
+            Severity::Warning,
+            IssueKind::TimingMismatch,
+            frame_index(first),
+            None,
+            format!(
+                "{} frames have a time_on that does not advance on the previous frame",
+                non_increasing
+            ),
+        );
+    }
+    let off_rate = timing.gaps.len() + timing.irregular_intervals.len();
+    if off_rate != 0 {
+        let first = timing
+            .gaps
+            .first()
+            .map(|gap| &gap.frame_index)
+            .into_iter()
+            .chain(timing.irregular_intervals.first())
+            .min();
+        report.push(
+            Severity::Warning,
+            IssueKind::TimingMismatch,
+            frame_index(first),
+            None,
+            format!(
+                "{} of {} frame intervals differ from the {}ms expected at {}fps",
+                off_rate,
+                timing.intervals,
+                timing.expected_interval_ms.round(),
+                fps
+            ),
+        );
+    }
+}
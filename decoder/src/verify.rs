@@ -0,0 +1,272 @@
+use crate::reader::CptvReader;
+use cptv_shared::v2::types::{Cptv2Header, CptvFrame};
+use serde::Serialize;
+use std::fmt;
+use std::fmt::{Debug, Display, Formatter};
+use std::io;
+
+/// A field that has a different value in the re-encoded file.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldMismatch {
+    pub field: &'static str,
+    pub original: String,
+    pub reencoded: String,
+}
+
+impl FieldMismatch {
+    fn new<T: Debug>(field: &'static str, original: &T, reencoded: &T) -> FieldMismatch {
+        FieldMismatch {
+            field,
+            original: format!("{:?}", original),
+            reencoded: format!("{:?}", reencoded),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PixelMismatch {
+    pub x: usize,
+    pub y: usize,
+    pub original: u16,
+    pub reencoded: u16,
+}
+
+/// The first frame that differs between the two files.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FrameMismatch {
+    // Index of the frame, counting every frame in the stream including any background frame.
+    #[serde(rename = "frameIndex")]
+    pub frame_index: usize,
+
+    /// Frame header fields that differ.  Bit width and frame size aren't compared, since they
+    /// depend on how the frame was packed rather than what it holds.
+    pub fields: Vec<FieldMismatch>,
+
+    /// The first pixel that differs, in row order.
+    pub pixel: Option<PixelMismatch>,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct RoundtripReport {
+    /// Header fields that differ.
+    #[serde(rename = "headerMismatches")]
+    pub header_mismatches: Vec<FieldMismatch>,
+
+    /// Number of frames in each file, including any background frame.
+    #[serde(rename = "originalFrames")]
+    pub original_frames: usize,
+    #[serde(rename = "reencodedFrames")]
+    pub reencoded_frames: usize,
+
+    #[serde(rename = "frameMismatch")]
+    pub frame_mismatch: Option<FrameMismatch>,
+
+    /// Set if either file couldn't be decoded; the comparison stops there.
+    pub error: Option<String>,
+}
+
+impl RoundtripReport {
+    /// True if both files decoded to exactly the same header, frames and pixels.
+    pub fn is_identical(&self) -> bool {
+        self.header_mismatches.is_empty() && self.frames_identical()
+    }
+
+    /// True if the frames are identical, and so are all the header fields other than `fields`,
+    /// for when some header fields were changed on purpose.
+    pub fn is_identical_except(&self, fields: &[&str]) -> bool {
+        self.frames_identical()
+            && self
+                .header_mismatches
+                .iter()
+                .all(|mismatch| fields.contains(&mismatch.field))
+    }
+
+    pub fn frames_identical(&self) -> bool {
+        self.error.is_none()
+            && self.frame_mismatch.is_none()
+            && self.original_frames == self.reencoded_frames
+    }
+}
+
+impl Display for RoundtripReport {
+    /// Describes the first difference found, if any.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(error) = &self.error {
+            return write!(f, "{}", error);
+        }
+        if let Some(mismatch) = self.header_mismatches.first() {
+            return write!(
+                f,
+                "Header field {} changed from {} to {}",
+                mismatch.field, mismatch.original, mismatch.reencoded
+            );
+        }
+        if let Some(frame) = &self.frame_mismatch {
+            if let Some(mismatch) = frame.fields.first() {
+                return write!(
+                    f,
+                    "Frame #{}: {} changed from {} to {}",
+                    frame.frame_index, mismatch.field, mismatch.original, mismatch.reencoded
+                );
+            }
+            if let Some(pixel) = &frame.pixel {
+                return write!(
+                    f,
+                    "Frame #{}: pixel {},{} changed from {} to {}",
+                    frame.frame_index, pixel.x, pixel.y, pixel.original, pixel.reencoded
+                );
+            }
+        }
+        if self.original_frames != self.reencoded_frames {
+            return write!(
+                f,
+                "{} frames became {}",
+                self.original_frames, self.reencoded_frames
+            );
+        }
+        write!(f, "Identical")
+    }
+}
+
+/// Decodes two gzipped CPTV files and checks that the second holds exactly what the first does:
+/// the same header fields, and the same frames with the same metadata and pixels.  Use it to
+/// make sure a re-encoded file lost nothing before it replaces the original.
+///
+/// Frames are compared as they are decoded, and the report describes the first frame that
+/// differs.  A truncated original is compared by the frames that could be recovered from it.
+pub fn verify_roundtrip(original: &[u8], reencoded: &[u8]) -> RoundtripReport {
+    let mut report = RoundtripReport::default();
+    if let Err(e) = compare(original, reencoded, &mut report) {
+        report.error = Some(e);
+    }
+    report
+}
+
+fn compare(original: &[u8], reencoded: &[u8], report: &mut RoundtripReport) -> Result<(), String> {
+    let open = |bytes, name| {
+        CptvReader::new(bytes).map_err(|e: io::Error| format!("Can't decode {} file: {}", name, e))
+    };
+    let mut original = open(original, "original")?;
+    let mut reencoded = open(reencoded, "re-encoded")?;
+
+    // Background frames come first in the stream, and so get the first frame index.
+    let mut frame_index = 0;
+    let backgrounds = (
+        original.background().cloned(),
+        reencoded.background().cloned(),
+    );
+    if backgrounds.0.is_some() || backgrounds.1.is_some() {
+        report.original_frames += backgrounds.0.is_some() as usize;
+        report.reencoded_frames += backgrounds.1.is_some() as usize;
+        if let (Some(a), Some(b)) = &backgrounds {
+            report.frame_mismatch = compare_frames(frame_index, a, b);
+        }
+        frame_index += 1;
+    }
+    loop {
+        let a = original
+            .next_frame()
+            .map_err(|e| format!("Can't decode original frame #{}: {}", frame_index, e))?;
+        let b = reencoded
+            .next_frame()
+            .map_err(|e| format!("Can't decode re-encoded frame #{}: {}", frame_index, e))?;
+        report.original_frames += a.is_some() as usize;
+        report.reencoded_frames += b.is_some() as usize;
+        match (a, b) {
+            (None, None) => break,
+            (Some(a), Some(b)) if report.frame_mismatch.is_none() => {
+                report.frame_mismatch = compare_frames(frame_index, a, b);
+            }
+            _ => {}
+        }
+        frame_index += 1;
+    }
+    // Compared last, since recovering a truncated file fills in its frame count.
+    report.header_mismatches = compare_headers(original.header(), reencoded.header());
+    Ok(())
+}
+
+macro_rules! compare_fields {
+    ($mismatches:expr, $a:expr, $b:expr, $($field:ident),*) => {
+        $(
+            if $a.$field != $b.$field {
+                $mismatches.push(FieldMismatch::new(stringify!($field), &$a.$field, &$b.$field));
+            }
+        )*
+    };
+}
+
+fn compare_headers(a: &Cptv2Header, b: &Cptv2Header) -> Vec<FieldMismatch> {
+    let mut mismatches = Vec::new();
+    compare_fields!(
+        mismatches,
+        a,
+        b,
+        timestamp,
+        width,
+        height,
+        compression,
+        device_name,
+        fps,
+        brand,
+        model,
+        device_id,
+        serial_number,
+        firmware_version,
+        motion_config,
+        preview_secs,
+        latitude,
+        longitude,
+        loc_timestamp,
+        altitude,
+        accuracy,
+        has_background_frame,
+        total_frame_count,
+        min_value,
+        max_value
+    );
+    mismatches
+}
+
+fn compare_frames(frame_index: usize, a: &CptvFrame, b: &CptvFrame) -> Option<FrameMismatch> {
+    let mut fields = Vec::new();
+    compare_fields!(
+        fields,
+        a,
+        b,
+        time_on,
+        last_ffc_time,
+        last_ffc_temp_c,
+        frame_temp_c,
+        is_background_frame
+    );
+    let (width, height) = (a.image_data.width(), a.image_data.height());
+    if (width, height) != (b.image_data.width(), b.image_data.height()) {
+        fields.push(FieldMismatch::new(
+            "dimensions",
+            &(width, height),
+            &(b.image_data.width(), b.image_data.height()),
+        ));
+    }
+    let pixel = a
+        .image_data
+        .data()
+        .iter()
+        .zip(b.image_data.data())
+        .position(|(a, b)| a != b)
+        .map(|i| PixelMismatch {
+            x: i % width.max(1),
+            y: i / width.max(1),
+            original: a.image_data.data()[i],
+            reencoded: b.image_data.data()[i],
+        });
+    if fields.is_empty() && pixel.is_none() {
+        None
+    } else {
+        Some(FrameMismatch {
+            frame_index,
+            fields,
+            pixel,
+        })
+    }
+}
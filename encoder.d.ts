@@ -20,3 +20,17 @@ export interface CptvFileParams {
 }
 
 export function createTestCptvFile(params: CptvFileParams): Promise<Uint8Array>;
+
+export interface RedactOptions {
+    // Size in degrees of the grid to snap latitude and longitude to (default 0.01).  Altitude is
+    // rounded to 100m and the location timestamp to the day.  null removes the location entirely.
+    grid?: number | null;
+    // Also remove the device name, device id and serial number (default false).
+    stripDevice?: boolean;
+}
+
+/**
+ * Returns a copy of a CPTV file with its location coarsened or removed, for sharing recordings
+ * without revealing exactly where they were made.  Frames are copied through untouched.
+ */
+export function redactCptvFile(file: Uint8Array, options?: RedactOptions): Promise<Uint8Array>;
@@ -45,3 +45,14 @@ export const createTestCptvFile = async (params) => {
   console.log('create using params', finalParams);
   return new Uint8Array(encoder.createTestCptvFile(finalParams));
 };
+
+export const redactCptvFile = async (file, options = {}) => {
+  if (!encoder) {
+    encoder = (await import("./encoder/pkg/encoder-node.cjs")).default;
+  }
+  if (typeof encoder.redactCptvFile !== "function") {
+    throw new Error("redactCptvFile isn't supported by this build of the encoder; rebuild it with build-encoder.sh");
+  }
+  const {grid = 0.01, stripDevice = false} = options;
+  return new Uint8Array(encoder.redactCptvFile(file, grid === null ? undefined : grid, stripDevice));
+};
@@ -0,0 +1,154 @@
+use cptv_shared::v2::tracking::BoundingBox;
+use cptv_shared::v2::types::{Clip, FrameData};
+use std::io;
+use std::io::ErrorKind;
+
+#[derive(Debug, Clone, Copy)]
+pub struct CropOptions {
+    /// Part of the frame to keep, or the whole frame if `None`.
+    pub region: Option<BoundingBox>,
+
+    /// Integer factor to scale the (cropped) frame down by.  Each pixel of the result is the
+    /// average of a `downscale` x `downscale` block, and any leftover rows or columns at the
+    /// right and bottom edges are dropped.
+    pub downscale: usize,
+}
+
+impl Default for CropOptions {
+    fn default() -> Self {
+        CropOptions {
+            region: None,
+            downscale: 1,
+        }
+    }
+}
+
+/// Crops and/or scales down every frame of a clip, including the background frame, and updates
+/// the dimensions and the range of pixel values in the header to match.
+pub fn crop_clip(mut clip: Clip, options: CropOptions) -> io::Result<Clip> {
+    let width = clip.header.width as usize;
+    let height = clip.header.height as usize;
+    let region = options.region.unwrap_or(BoundingBox {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    });
+    let fits = |start: usize, size: usize, limit: usize| {
+        size != 0 && start.checked_add(size).is_some_and(|end| end <= limit)
+    };
+    if !fits(region.x, region.width, width) || !fits(region.y, region.height, height) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Crop region {}x{} at {},{} doesn't fit in a {}x{} frame",
+                region.width, region.height, region.x, region.y, width, height
+            ),
+        ));
+    }
+    let downscale = options.downscale.max(1);
+    if region.width < downscale || region.height < downscale {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Can't scale a {}x{} frame down by {}",
+                region.width, region.height, downscale
+            ),
+        ));
+    }
+
+    for frame in clip.background.iter_mut().chain(clip.frames.iter_mut()) {
+        frame.image_data = crop_frame(&frame.image_data, region, downscale);
+    }
+    clip.header.width = (region.width / downscale) as u32;
+    clip.header.height = (region.height / downscale) as u32;
+    clip.update_value_range();
+    Ok(clip)
+}
+
+fn crop_frame(frame: &FrameData, region: BoundingBox, downscale: usize) -> FrameData {
+    let width = region.width / downscale;
+    let height = region.height / downscale;
+    let block_size = (downscale * downscale) as u64;
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let mut total = 0u64;
+            for block_y in 0..downscale {
+                let row = (region.y + y * downscale + block_y) * frame.width();
+                let start = row + region.x + x * downscale;
+                total += frame.data()[start..start + downscale]
+                    .iter()
+                    .map(|value| *value as u64)
+                    .sum::<u64>();
+            }
+            pixels.push(((total + block_size / 2) / block_size) as u16);
+        }
+    }
+    FrameData::with_dimensions_and_data(width, height, &pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cptv_shared::v2::types::{Cptv2Header, CptvFrame};
+
+    const WIDTH: usize = 6;
+    const HEIGHT: usize = 4;
+
+    fn clip() -> Clip {
+        let mut header = Cptv2Header::new();
+        header.width = WIDTH as u32;
+        header.height = HEIGHT as u32;
+        header.min_value = Some(0);
+        header.max_value = Some(u16::MAX);
+        let mut frame = CptvFrame::new_with_dimensions(WIDTH, HEIGHT);
+        let pixels: Vec<u16> = (0..WIDTH * HEIGHT)
+            .map(|pixel| 1000 + pixel as u16)
+            .collect();
+        frame.image_data = FrameData::with_dimensions_and_data(WIDTH, HEIGHT, &pixels);
+        Clip {
+            header,
+            background: None,
+            frames: vec![frame],
+        }
+    }
+
+    #[test]
+    fn updates_the_range_of_pixel_values() {
+        let options = CropOptions {
+            region: Some(BoundingBox {
+                x: 1,
+                y: 1,
+                width: 2,
+                height: 2,
+            }),
+            downscale: 1,
+        };
+        let cropped = crop_clip(clip(), options).unwrap();
+        assert_eq!((cropped.header.width, cropped.header.height), (2, 2));
+        assert_eq!(
+            cropped.frames[0].image_data.data(),
+            [1007, 1008, 1013, 1014]
+        );
+        assert_eq!(cropped.header.min_value, Some(1007));
+        assert_eq!(cropped.header.max_value, Some(1014));
+    }
+
+    #[test]
+    fn refuses_regions_that_overflow() {
+        for (x, y) in [(usize::MAX, 0), (0, usize::MAX)] {
+            let options = CropOptions {
+                region: Some(BoundingBox {
+                    x,
+                    y,
+                    width: 2,
+                    height: 2,
+                }),
+                downscale: 1,
+            };
+            let error = crop_clip(clip(), options).expect_err("region doesn't fit");
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        }
+    }
+}
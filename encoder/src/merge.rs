@@ -0,0 +1,199 @@
+use cptv_shared::v2::types::{Clip, Cptv2Header};
+use std::io;
+use std::io::ErrorKind;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MergeOptions {
+    /// Keep the real time that passed between recordings in `time_on`, rather than starting
+    /// each clip one frame after the previous one ends.
+    pub keep_gaps: bool,
+}
+
+/// Joins consecutive recordings from the same camera into a single clip.
+///
+/// All clips must have the same resolution and frame rate, and come from the same device.  The
+/// result uses the first clip's header, and the `time_on` and `last_ffc_time` of each later clip
+/// are shifted so that time runs on from the end of the clip before it.  Background frames of
+/// later clips are dropped.
+///
+/// The first frame of each later clip is marked as an intra frame, so when the result is written
+/// with `encode_clip` it's encoded on its own, as it was in its original file, rather than
+/// against the last frame of the clip before.  Decoding can start again at each join, and damage
+/// before a join doesn't spoil the frames after it.  Decoders that don't know intra frames can't
+/// read anything after the first join, though.
+pub fn merge_clips(clips: Vec<Clip>, options: MergeOptions) -> io::Result<Clip> {
+    let mut clips = clips.into_iter();
+    let mut merged = clips
+        .next()
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "No clips to merge"))?;
+    // These describe the first clip, not the merged one.
+    merged.header.total_frame_count = None;
+    merged.header.min_value = None;
+    merged.header.max_value = None;
+
+    let frame_interval = 1000 / merged.header.fps.max(1) as i64;
+    // When the previous clip started, in microseconds since the epoch, and the (shifted)
+    // `time_on` of its first frame.
+    let mut prev_timestamp = merged.header.timestamp;
+    let mut prev_time_on = merged.frames.first().map(|frame| frame.time_on);
+
+    for (index, clip) in clips.enumerate() {
+        check_compatible(&merged.header, &clip.header, index + 1)?;
+        let first_time_on = match clip.frames.first() {
+            Some(frame) => frame.time_on,
+            None => continue,
+        };
+        let offset = match (merged.frames.last(), prev_time_on) {
+            (Some(last), Some(prev_time_on)) => {
+                let mut step = frame_interval;
+                if options.keep_gaps {
+                    let prev_duration = last.time_on.saturating_sub(prev_time_on) as i64 * 1000;
+                    let gap = (clip.header.timestamp as i64 - prev_timestamp as i64 - prev_duration)
+                        / 1000;
+                    step = step.max(gap);
+                }
+                last.time_on as i64 + step - first_time_on as i64
+            }
+            _ => 0,
+        };
+        prev_timestamp = clip.header.timestamp;
+        prev_time_on = Some(shift(first_time_on, offset));
+        for (frame_index, mut frame) in clip.frames.into_iter().enumerate() {
+            if frame_index == 0 {
+                frame.is_intra_frame = true;
+            }
+            frame.time_on = shift(frame.time_on, offset);
+            frame.last_ffc_time = frame
+                .last_ffc_time
+                .map(|last_ffc_time| shift(last_ffc_time, offset));
+            merged.frames.push(frame);
+        }
+    }
+    Ok(merged)
+}
+
+fn check_compatible(first: &Cptv2Header, other: &Cptv2Header, index: usize) -> io::Result<()> {
+    if (first.width, first.height) != (other.width, other.height) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Clip #{} is {}x{}, but the first clip is {}x{}",
+                index, other.width, other.height, first.width, first.height
+            ),
+        ));
+    }
+    if first.fps != other.fps {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Clip #{} is {} fps, but the first clip is {} fps",
+                index, other.fps, first.fps
+            ),
+        ));
+    }
+    if first.device_name != other.device_name
+        || first.device_id != other.device_id
+        || first.serial_number != other.serial_number
+    {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Clip #{} is from device '{}' ({:?}), but the first clip is from '{}' ({:?})",
+                index, other.device_name, other.device_id, first.device_name, first.device_id
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn shift(time: u32, offset: i64) -> u32 {
+    (time as i64 + offset).clamp(0, u32::MAX as i64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode_clip;
+    use cptv_shared::v2::types::{CptvFrame, FrameData};
+    use cptv_shared::v2::{decode_cptv2_header, decode_frame_header_v2, try_unpack_frame_v2};
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    const WIDTH: usize = 6;
+    const HEIGHT: usize = 4;
+
+    fn clip(fps: u8, base: u16, frames: usize) -> Clip {
+        let mut header = Cptv2Header::new();
+        header.width = WIDTH as u32;
+        header.height = HEIGHT as u32;
+        header.fps = fps;
+        let frames = (0..frames)
+            .map(|index| {
+                let pixels: Vec<u16> = (0..WIDTH * HEIGHT)
+                    .map(|pixel| base + ((pixel * 13 + index * 7) % 50) as u16)
+                    .collect();
+                let mut frame = CptvFrame::new_with_dimensions(WIDTH, HEIGHT);
+                frame.image_data = FrameData::with_dimensions_and_data(WIDTH, HEIGHT, &pixels);
+                frame.time_on = 1000 + 111 * index as u32;
+                frame
+            })
+            .collect();
+        Clip {
+            header,
+            background: None,
+            frames,
+        }
+    }
+
+    #[test]
+    fn later_clips_start_with_an_intra_frame() {
+        let merged = merge_clips(
+            vec![clip(9, 29000, 3), clip(9, 31000, 2), clip(9, 30000, 2)],
+            MergeOptions::default(),
+        )
+        .unwrap();
+        let intra: Vec<_> = merged
+            .frames
+            .iter()
+            .map(|frame| frame.is_intra_frame)
+            .collect();
+        assert_eq!(intra, [false, false, false, true, false, true, false]);
+
+        let mut stream = Vec::new();
+        GzDecoder::new(&encode_clip(&merged)[..])
+            .read_to_end(&mut stream)
+            .unwrap();
+        let (mut input, _) = decode_cptv2_header(&stream[5..]).unwrap();
+        let mut prev_frame = None;
+        for (index, expected) in merged.frames.iter().enumerate() {
+            let (rest, (data, mut frame)) =
+                decode_frame_header_v2(input, WIDTH, HEIGHT, false).unwrap();
+            assert_eq!(frame.is_intra_frame, expected.is_intra_frame);
+            // Intra frames decode the same without the frames before them.
+            if frame.is_intra_frame {
+                prev_frame = None;
+            }
+            assert!(try_unpack_frame_v2(&prev_frame, data, &mut frame));
+            assert_eq!(
+                frame.image_data.data(),
+                expected.image_data.data(),
+                "frame #{}",
+                index
+            );
+            prev_frame = Some(frame);
+            input = rest;
+        }
+    }
+
+    #[test]
+    fn refuses_clips_that_dont_match() {
+        let mismatched_fps =
+            merge_clips(vec![clip(9, 0, 2), clip(8, 0, 2)], MergeOptions::default());
+        assert_eq!(mismatched_fps.unwrap_err().kind(), ErrorKind::InvalidInput);
+
+        let mut smaller = clip(9, 0, 2);
+        smaller.header.width -= 1;
+        let mismatched_size = merge_clips(vec![clip(9, 0, 2), smaller], MergeOptions::default());
+        assert_eq!(mismatched_size.unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
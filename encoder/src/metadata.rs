@@ -0,0 +1,326 @@
+use crate::push_header;
+use cptv_shared::v2::decode_cptv2_header;
+use cptv_shared::v2::types::Cptv2Header;
+use cptv_shared::CptvHeader;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+
+/// Header fields to change.  Fields left as `None` are kept as they are.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderEdits {
+    pub device_name: Option<String>,
+    pub device_id: Option<u32>,
+    pub latitude: Option<f32>,
+    pub longitude: Option<f32>,
+    pub altitude: Option<f32>,
+    pub accuracy: Option<f32>,
+    pub loc_timestamp: Option<u64>,
+
+    /// Microseconds to add to the recording timestamp, for cameras whose clock was wrong.
+    pub timestamp_offset: Option<i64>,
+}
+
+impl HeaderEdits {
+    pub fn is_empty(&self) -> bool {
+        self.device_name.is_none()
+            && self.device_id.is_none()
+            && self.latitude.is_none()
+            && self.longitude.is_none()
+            && self.altitude.is_none()
+            && self.accuracy.is_none()
+            && self.loc_timestamp.is_none()
+            && self.timestamp_offset.is_none()
+    }
+
+    /// Names of the `Cptv2Header` fields these edits change.
+    pub fn changed_fields(&self) -> Vec<&'static str> {
+        [
+            ("device_name", self.device_name.is_some()),
+            ("device_id", self.device_id.is_some()),
+            ("latitude", self.latitude.is_some()),
+            ("longitude", self.longitude.is_some()),
+            ("altitude", self.altitude.is_some()),
+            ("accuracy", self.accuracy.is_some()),
+            ("loc_timestamp", self.loc_timestamp.is_some()),
+            ("timestamp", self.timestamp_offset.is_some()),
+        ]
+        .iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(field, _)| *field)
+        .collect()
+    }
+
+    pub fn apply(&self, header: &mut Cptv2Header) {
+        if let Some(device_name) = &self.device_name {
+            header.device_name = device_name.clone();
+        }
+        header.device_id = self.device_id.or(header.device_id);
+        header.latitude = self.latitude.or(header.latitude);
+        header.longitude = self.longitude.or(header.longitude);
+        header.altitude = self.altitude.or(header.altitude);
+        header.accuracy = self.accuracy.or(header.accuracy);
+        header.loc_timestamp = self.loc_timestamp.or(header.loc_timestamp);
+        if let Some(offset) = self.timestamp_offset {
+            header.timestamp = (header.timestamp as i64 + offset).max(0) as u64;
+        }
+    }
+}
+
+/// Applies `edits` to the header of a gzipped CPTV file.
+pub fn edit_header(file: &[u8], edits: &HeaderEdits) -> io::Result<Vec<u8>> {
+    rewrite_header(file, |header| edits.apply(header))
+}
+
+/// Re-encodes just the header of a gzipped CPTV file, after passing it to `edit`.  The frames
+/// are copied through byte for byte, without being decoded.
+///
+/// The new file is read back before it's returned, and must hold exactly the edited header and
+/// the original frames, so it's safe to replace the original with.  Header fields this version
+/// doesn't know about are copied through byte for byte, after the ones it rewrites.
+pub fn rewrite_header<F: FnOnce(&mut Cptv2Header)>(file: &[u8], edit: F) -> io::Result<Vec<u8>> {
+    let mut input = Vec::new();
+    GzDecoder::new(file).read_to_end(&mut input)?;
+    if input.len() < 5 || &input[0..4] != b"CPTV" || !matches!(input[4], 1 | 2) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "Not a CPTV v1 or v2 file",
+        ));
+    }
+    let (frames, mut header) = match decode_cptv2_header(&input[5..]) {
+        Ok((frames, CptvHeader::V2(header))) => (frames, header),
+        _ => {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Unable to parse CPTV header",
+            ))
+        }
+    };
+    let unknown_fields = unknown_header_fields(&input[5..input.len() - frames.len()]);
+    edit(&mut header);
+
+    let mut output = Vec::with_capacity(input.len());
+    push_header(&mut output, &CptvHeader::V2(header.clone()));
+    for field in &unknown_fields {
+        output[HEADER_FIELD_COUNT_POS] =
+            output[HEADER_FIELD_COUNT_POS]
+                .checked_add(1)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "Too many header fields to keep unknown header field '{}'",
+                            field[1] as char
+                        ),
+                    )
+                })?;
+        output.extend_from_slice(field);
+    }
+    output.extend_from_slice(frames);
+
+    let mut buffer = Vec::new();
+    {
+        let mut encoder = GzEncoder::new(&mut buffer, flate2::Compression::default());
+        encoder.write_all(&output)?;
+    }
+    verify_rewrite(&buffer, &header, &unknown_fields, frames)?;
+    Ok(buffer)
+}
+
+// Where `push_header` writes the number of header fields: after "CPTV", the version and 'H'.
+const HEADER_FIELD_COUNT_POS: usize = 6;
+
+// The header fields `decode_cptv2_header` reads into a `Cptv2Header`, which are the ones
+// `push_header` writes back.
+const KNOWN_HEADER_FIELDS: &[u8] = b"TXYCDZNVEBIMPLOSAUJQKg";
+
+// The raw bytes (length, code and value) of each field in a parsed header that isn't read into
+// a `Cptv2Header`, in the order they appear.
+fn unknown_header_fields(header: &[u8]) -> Vec<&[u8]> {
+    let num_fields = header.get(1).copied().unwrap_or(0);
+    let mut fields = Vec::new();
+    let mut offset = 2;
+    for _ in 0..num_fields {
+        let field = match header.get(offset..offset + 2) {
+            Some(&[length, _]) => header.get(offset..offset + 2 + length as usize),
+            _ => None,
+        };
+        let field = match field {
+            Some(field) => field,
+            None => break,
+        };
+        if !KNOWN_HEADER_FIELDS.contains(&field[1]) {
+            fields.push(field);
+        }
+        offset += field.len();
+    }
+    fields
+}
+
+// Checks that a rewritten file decodes to `header` with `unknown_fields` kept as they were,
+// followed by exactly the `frames` bytes.
+fn verify_rewrite(
+    file: &[u8],
+    header: &Cptv2Header,
+    unknown_fields: &[&[u8]],
+    frames: &[u8],
+) -> io::Result<()> {
+    let mismatch = |what: String| {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Rewritten file doesn't match: {}", what),
+        )
+    };
+    let mut written = Vec::new();
+    GzDecoder::new(file).read_to_end(&mut written)?;
+    let (written_frames, written_header) =
+        match decode_cptv2_header(written.get(5..).unwrap_or_default()) {
+            Ok((frames, CptvHeader::V2(header))) => (frames, header),
+            _ => return Err(mismatch("its header can't be parsed".to_string())),
+        };
+    let fields = |header: &Cptv2Header| match serde_json::to_value(header) {
+        Ok(serde_json::Value::Object(fields)) => fields,
+        _ => serde_json::Map::new(),
+    };
+    let (expected, written_fields) = (fields(header), fields(&written_header));
+    if let Some((field, value)) = expected
+        .iter()
+        .find(|(field, value)| written_fields.get(*field) != Some(*value))
+    {
+        return Err(mismatch(format!(
+            "header field {} should be {} but is {}",
+            field,
+            value,
+            written_fields
+                .get(field)
+                .unwrap_or(&serde_json::Value::Null)
+        )));
+    }
+    let written_unknown_fields =
+        unknown_header_fields(&written[5..written.len() - written_frames.len()]);
+    if let Some(field) = unknown_fields
+        .iter()
+        .find(|field| !written_unknown_fields.contains(field))
+    {
+        return Err(mismatch(format!(
+            "unknown header field '{}' wasn't kept",
+            field[1] as char
+        )));
+    }
+    if written_frames != frames {
+        return Err(mismatch("the frames changed".to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode_clip;
+    use cptv_shared::v2::types::{Clip, CptvFrame, FrameData};
+
+    fn test_file() -> Vec<u8> {
+        let mut header = Cptv2Header::new();
+        header.width = 3;
+        header.height = 2;
+        header.device_name = "test".to_string();
+        header.latitude = Some(-43.5);
+        let frames = (0..3)
+            .map(|index| {
+                let mut frame = CptvFrame::new_with_dimensions(3, 2);
+                let pixels: Vec<u16> = (0..6).map(|pixel| 1000 * index + pixel).collect();
+                frame.image_data = FrameData::with_dimensions_and_data(3, 2, &pixels);
+                frame.time_on = 1000 + 111 * index as u32;
+                frame
+            })
+            .collect();
+        encode_clip(&Clip {
+            header,
+            background: None,
+            frames,
+        })
+    }
+
+    // The header and frame bytes of a gzipped CPTV file.
+    fn split(file: &[u8]) -> (Cptv2Header, Vec<u8>) {
+        let mut input = Vec::new();
+        GzDecoder::new(file).read_to_end(&mut input).unwrap();
+        match decode_cptv2_header(&input[5..]) {
+            Ok((frames, CptvHeader::V2(header))) => (header, frames.to_vec()),
+            _ => panic!("Unable to parse CPTV header"),
+        }
+    }
+
+    #[test]
+    fn edits_the_header_and_keeps_the_frames() {
+        let file = test_file();
+        let edits = HeaderEdits {
+            device_name: Some("renamed".to_string()),
+            latitude: Some(-41.25),
+            ..HeaderEdits::default()
+        };
+        let edited = edit_header(&file, &edits).unwrap();
+        let ((original, original_frames), (header, frames)) = (split(&file), split(&edited));
+        assert_eq!(header.device_name, "renamed");
+        assert_eq!(header.latitude, Some(-41.25));
+        assert_eq!(header.timestamp, original.timestamp);
+        assert_eq!(frames, original_frames);
+    }
+
+    #[test]
+    fn verification_catches_changed_headers_and_frames() {
+        let file = test_file();
+        let (header, frames) = split(&file);
+        assert!(verify_rewrite(&file, &header, &[], &frames).is_ok());
+
+        let mut renamed = header.clone();
+        renamed.device_name = "renamed".to_string();
+        let error = verify_rewrite(&file, &renamed, &[], &frames).expect_err("header differs");
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("deviceName"), "{}", error);
+
+        let mut changed = frames.clone();
+        *changed.last_mut().unwrap() ^= 1;
+        let error = verify_rewrite(&file, &header, &[], &changed).expect_err("frames differ");
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        let error = verify_rewrite(&file, &header, &[&[1, b'W', 7]], &frames)
+            .expect_err("unknown field dropped");
+        assert!(error.to_string().contains("'W'"), "{}", error);
+    }
+
+    #[test]
+    fn keeps_unknown_header_fields() {
+        // Add a field from a newer version of the format to the end of the header.
+        let mut input = Vec::new();
+        GzDecoder::new(&test_file()[..])
+            .read_to_end(&mut input)
+            .unwrap();
+        let frames_start = input.len() - split(&test_file()).1.len();
+        let unknown_field = [3, b'W', 1, 2, 3];
+        input[HEADER_FIELD_COUNT_POS] += 1;
+        input.splice(frames_start..frames_start, unknown_field.iter().copied());
+        let mut file = Vec::new();
+        GzEncoder::new(&mut file, flate2::Compression::default())
+            .write_all(&input)
+            .unwrap();
+
+        let edits = HeaderEdits {
+            device_name: Some("renamed".to_string()),
+            ..HeaderEdits::default()
+        };
+        let edited = edit_header(&file, &edits).unwrap();
+        let mut output = Vec::new();
+        GzDecoder::new(&edited[..])
+            .read_to_end(&mut output)
+            .unwrap();
+        let (header, frames) = split(&edited);
+        assert_eq!(header.device_name, "renamed");
+        assert_eq!(
+            unknown_header_fields(&output[5..output.len() - frames.len()]),
+            [&unknown_field[..]]
+        );
+        assert_eq!(frames, split(&file).1);
+    }
+}
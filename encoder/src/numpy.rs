@@ -0,0 +1,451 @@
+use cptv_shared::v2::types::{Clip, Cptv2Header, CptvFrame, FrameData};
+use std::io;
+use std::io::{ErrorKind, Read, Seek, Write};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// Writes a clip to a NumPy `.npz` archive, as loaded by `numpy.load`:
+///
+/// - `frames`: `uint16` array of shape (N, height, width)
+/// - `time_on`: `uint32` milliseconds
+/// - `last_ffc_time`: `int64` milliseconds, or -1 where unknown
+/// - `frame_temp_c`, `last_ffc_temp_c`: `float32`, or NaN where unknown
+/// - `is_background`: `bool`, true for the background frame, which comes first if there is one
+/// - `header`: the header as a JSON string, in the same form as `cptv to-json` uses
+pub fn write_npz<W: Write + Seek>(clip: &Clip, writer: W) -> io::Result<()> {
+    let frames: Vec<&CptvFrame> = clip.background.iter().chain(clip.frames.iter()).collect();
+    let count = frames.len();
+    let (width, height) = (clip.header.width as usize, clip.header.height as usize);
+
+    let mut zip = ZipWriter::new(writer);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut add = |name: &str, array: Vec<u8>| -> io::Result<()> {
+        zip.start_file(format!("{}.npy", name), options)
+            .map_err(io::Error::other)?;
+        zip.write_all(&array)
+    };
+
+    let mut pixels = Vec::with_capacity(count * width * height * 2);
+    for frame in &frames {
+        for value in frame.image_data.data() {
+            pixels.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    add("frames", npy("<u2", &[count, height, width], &pixels))?;
+    add(
+        "time_on",
+        npy(
+            "<u4",
+            &[count],
+            &le_bytes(&frames, |frame| frame.time_on.to_le_bytes()),
+        ),
+    )?;
+    add(
+        "last_ffc_time",
+        npy(
+            "<i8",
+            &[count],
+            &le_bytes(&frames, |frame| {
+                frame
+                    .last_ffc_time
+                    .map_or(-1, |time| time as i64)
+                    .to_le_bytes()
+            }),
+        ),
+    )?;
+    add(
+        "frame_temp_c",
+        npy(
+            "<f4",
+            &[count],
+            &le_bytes(&frames, |frame| {
+                frame.frame_temp_c.unwrap_or(f32::NAN).to_le_bytes()
+            }),
+        ),
+    )?;
+    add(
+        "last_ffc_temp_c",
+        npy(
+            "<f4",
+            &[count],
+            &le_bytes(&frames, |frame| {
+                frame.last_ffc_temp_c.unwrap_or(f32::NAN).to_le_bytes()
+            }),
+        ),
+    )?;
+    add(
+        "is_background",
+        npy(
+            "|b1",
+            &[count],
+            &le_bytes(&frames, |frame| [frame.is_background_frame as u8]),
+        ),
+    )?;
+
+    let header = serde_json::to_string(&clip.header).map_err(io::Error::other)?;
+    let header: Vec<u8> = header
+        .chars()
+        .flat_map(|c| (c as u32).to_le_bytes())
+        .collect();
+    add(
+        "header",
+        npy(&format!("<U{}", header.len() / 4), &[], &header),
+    )?;
+
+    zip.finish().map_err(io::Error::other)?;
+    Ok(())
+}
+
+/// Reads a clip back from a `.npz` archive laid out as `write_npz` writes it.  Only `frames` is
+/// required; the frame dimensions are taken from its shape, and other arrays may be any integer,
+/// float or bool type NumPy writes.  Without a `header`, a default one is used.
+pub fn read_npz<R: Read + Seek>(reader: R) -> io::Result<Clip> {
+    let mut zip = ZipArchive::new(reader).map_err(invalid)?;
+    let mut read = |name: &str| -> io::Result<Option<NpyArray>> {
+        let mut file = match zip.by_name(&format!("{}.npy", name)) {
+            Ok(file) => file,
+            Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+            Err(e) => return Err(invalid(e)),
+        };
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        NpyArray::parse(&bytes)
+            .map(Some)
+            .map_err(|e| invalid(format!("{}.npy: {}", name, e)))
+    };
+
+    let frames = read("frames")?.ok_or_else(|| invalid("Missing frames.npy"))?;
+    let (count, height, width) = match frames.shape[..] {
+        [count, height, width] if width > 0 && height > 0 => (count, height, width),
+        _ => return Err(invalid("frames.npy must have shape (N, height, width)")),
+    };
+    let frame_size = width
+        .checked_mul(height)
+        .filter(|_| width <= u32::MAX as usize && height <= u32::MAX as usize)
+        .ok_or_else(|| invalid("frames.npy has frames too large to decode"))?;
+    let mut header = match read("header")? {
+        Some(header) => {
+            let json = header
+                .string
+                .ok_or_else(|| invalid("header.npy must be a string"))?;
+            serde_json::from_str::<Cptv2Header>(&json).map_err(invalid)?
+        }
+        None => Cptv2Header::new(),
+    };
+    header.width = width as u32;
+    header.height = height as u32;
+
+    let column = |array: Option<NpyArray>, name: &str| -> io::Result<Option<Vec<f64>>> {
+        match array {
+            Some(array) if array.values.len() != count => Err(invalid(format!(
+                "{}.npy has {} values, but there are {} frames",
+                name,
+                array.values.len(),
+                count
+            ))),
+            array => Ok(array.map(|array| array.values)),
+        }
+    };
+    let time_on = column(read("time_on")?, "time_on")?;
+    let last_ffc_time = column(read("last_ffc_time")?, "last_ffc_time")?;
+    let frame_temp_c = column(read("frame_temp_c")?, "frame_temp_c")?;
+    let last_ffc_temp_c = column(read("last_ffc_temp_c")?, "last_ffc_temp_c")?;
+    let is_background = column(read("is_background")?, "is_background")?;
+
+    let mut clip = Clip {
+        header,
+        background: None,
+        frames: Vec::with_capacity(count),
+    };
+    for (index, values) in frames.values.chunks(frame_size).enumerate() {
+        let mut pixels = Vec::with_capacity(values.len());
+        for value in values {
+            if !(0.0..=u16::MAX as f64).contains(value) {
+                return Err(invalid(format!(
+                    "Frame #{} has pixel value {} outside the u16 range",
+                    index, value
+                )));
+            }
+            pixels.push(value.round() as u16);
+        }
+        let mut frame = CptvFrame::new_with_dimensions(width, height);
+        frame.image_data = FrameData::with_dimensions_and_data(width, height, &pixels);
+        let at = |column: &Option<Vec<f64>>| column.as_ref().map(|column| column[index]);
+        frame.time_on = at(&time_on).map_or(0, |time| time as u32);
+        frame.last_ffc_time = at(&last_ffc_time)
+            .filter(|time| *time >= 0.0)
+            .map(|time| time as u32);
+        frame.frame_temp_c = at(&frame_temp_c)
+            .filter(|temp| !temp.is_nan())
+            .map(|temp| temp as f32);
+        frame.last_ffc_temp_c = at(&last_ffc_temp_c)
+            .filter(|temp| !temp.is_nan())
+            .map(|temp| temp as f32);
+        frame.is_background_frame = at(&is_background).is_some_and(|value| value != 0.0);
+
+        if frame.is_background_frame {
+            if index != 0 {
+                return Err(invalid(format!(
+                    "Frame #{} is a background frame, but only the first frame can be",
+                    index
+                )));
+            }
+            clip.background = Some(frame);
+        } else {
+            clip.frames.push(frame);
+        }
+    }
+    clip.header.has_background_frame = clip.background.is_some();
+    Ok(clip)
+}
+
+fn invalid<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, error)
+}
+
+fn le_bytes<const N: usize, F: Fn(&CptvFrame) -> [u8; N]>(
+    frames: &[&CptvFrame],
+    to_bytes: F,
+) -> Vec<u8> {
+    frames.iter().flat_map(|frame| to_bytes(frame)).collect()
+}
+
+// A version 1.0 `.npy` file holding a C ordered array.
+fn npy(descr: &str, shape: &[usize], data: &[u8]) -> Vec<u8> {
+    let shape = match shape {
+        [length] => format!("({},)", length),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(|length| length.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        descr, shape
+    );
+    // The header is padded so the data starts on a 64 byte boundary, and ends with a newline.
+    let unpadded = NPY_MAGIC.len() + 4 + header.len() + 1;
+    header.extend(std::iter::repeat_n(
+        ' ',
+        unpadded.next_multiple_of(64) - unpadded,
+    ));
+    header.push('\n');
+
+    let mut output = Vec::with_capacity(NPY_MAGIC.len() + 4 + header.len() + data.len());
+    output.extend_from_slice(NPY_MAGIC);
+    output.extend_from_slice(&[1, 0]);
+    output.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    output.extend_from_slice(header.as_bytes());
+    output.extend_from_slice(data);
+    output
+}
+
+const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+
+// The contents of a `.npy` file: numbers (and bools) as f64s, or a single unicode string.
+struct NpyArray {
+    shape: Vec<usize>,
+    values: Vec<f64>,
+    string: Option<String>,
+}
+
+impl NpyArray {
+    fn parse(bytes: &[u8]) -> Result<NpyArray, String> {
+        if bytes.len() < 10 || &bytes[..6] != NPY_MAGIC {
+            return Err("Not a .npy file".to_string());
+        }
+        let (header_length, header_start) = match bytes[6] {
+            1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+            2 | 3 if bytes.len() >= 12 => (
+                u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize,
+                12,
+            ),
+            version => return Err(format!("Unsupported .npy version {}", version)),
+        };
+        let data_start = header_start + header_length;
+        let header = bytes
+            .get(header_start..data_start)
+            .map(String::from_utf8_lossy)
+            .ok_or("Truncated .npy header")?;
+        let descr = header_value(&header, "descr")
+            .map(|descr| descr.trim_matches(['\'', '"']).to_string())
+            .ok_or("Missing descr in .npy header")?;
+        if header_value(&header, "fortran_order") == Some("True") {
+            return Err("Fortran ordered arrays aren't supported".to_string());
+        }
+        let shape = header_value(&header, "shape")
+            .ok_or("Missing shape in .npy header")?
+            .trim_matches(['(', ')'])
+            .split(',')
+            .map(str::trim)
+            .filter(|length| !length.is_empty())
+            .map(|length| length.parse::<usize>().map_err(|e| e.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let count = shape
+            .iter()
+            .try_fold(1usize, |count, length| count.checked_mul(*length))
+            .ok_or("Array shape is too large")?;
+        let data = &bytes[data_start..];
+
+        if let Some(chars) = descr.strip_prefix("<U") {
+            let chars: usize = chars.parse().map_err(|_| format!("Bad dtype {}", descr))?;
+            let string = data
+                .chunks_exact(4)
+                .take(chars)
+                .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                .take_while(|c| *c != 0)
+                .map(|c| char::from_u32(c).ok_or("Invalid character in string"))
+                .collect::<Result<String, _>>()?;
+            return Ok(NpyArray {
+                shape,
+                values: Vec::new(),
+                string: Some(string),
+            });
+        }
+
+        let kind = descr.get(1..).unwrap_or("");
+        let size = match kind {
+            "b1" | "u1" | "i1" => 1,
+            "u2" | "i2" => 2,
+            "u4" | "i4" | "f4" => 4,
+            "u8" | "i8" | "f8" => 8,
+            _ => return Err(format!("Unsupported dtype {}", descr)),
+        };
+        if descr.starts_with('>') && size > 1 {
+            return Err(format!("Big endian dtype {} isn't supported", descr));
+        }
+        let length = count
+            .checked_mul(size)
+            .filter(|length| *length <= data.len())
+            .ok_or_else(|| {
+                format!(
+                    "Expected {} values, but the data is only {} bytes",
+                    count,
+                    data.len()
+                )
+            })?;
+        let values = data[..length]
+            .chunks_exact(size)
+            .map(|b| match kind {
+                "b1" | "u1" => b[0] as f64,
+                "i1" => b[0] as i8 as f64,
+                "u2" => u16::from_le_bytes([b[0], b[1]]) as f64,
+                "i2" => i16::from_le_bytes([b[0], b[1]]) as f64,
+                "u4" => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                "i4" => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                "f4" => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                "u8" => u64::from_le_bytes(b.try_into().unwrap()) as f64,
+                "i8" => i64::from_le_bytes(b.try_into().unwrap()) as f64,
+                _ => f64::from_le_bytes(b.try_into().unwrap()),
+            })
+            .collect();
+        Ok(NpyArray {
+            shape,
+            values,
+            string: None,
+        })
+    }
+}
+
+// The text of `key`'s value in a `.npy` header dict, e.g. `'<u2'` or `(3, 4)`.
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header.find(&format!("'{}'", key))? + key.len() + 2;
+    let rest = header[start..].trim_start().strip_prefix(':')?.trim_start();
+    let end = if rest.starts_with('(') {
+        rest.find(')')? + 1
+    } else if let Some(quoted) = rest.strip_prefix('\'') {
+        quoted.find('\'')? + 2
+    } else {
+        rest.find([',', '}'])?
+    };
+    Some(rest[..end].trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn test_clip() -> Clip {
+        let mut header = Cptv2Header::new();
+        header.width = 3;
+        header.height = 2;
+        header.device_name = "test".to_string();
+        let mut background = CptvFrame::new_with_dimensions(3, 2);
+        background.image_data = FrameData::with_dimensions_and_data(3, 2, &[1, 2, 3, 4, 5, 6]);
+        background.is_background_frame = true;
+        header.has_background_frame = true;
+        let frames = (0..3)
+            .map(|index| {
+                let mut frame = CptvFrame::new_with_dimensions(3, 2);
+                let pixels: Vec<u16> = (0..6).map(|pixel| 1000 * index + pixel).collect();
+                frame.image_data = FrameData::with_dimensions_and_data(3, 2, &pixels);
+                frame.time_on = 1000 + 111 * index as u32;
+                frame.last_ffc_time = (index > 0).then_some(900);
+                frame.frame_temp_c = Some(25.5);
+                frame
+            })
+            .collect();
+        Clip {
+            header,
+            background: Some(background),
+            frames,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let clip = test_clip();
+        let mut npz = Cursor::new(Vec::new());
+        write_npz(&clip, &mut npz).unwrap();
+        npz.set_position(0);
+        let read = read_npz(npz).unwrap();
+
+        assert_eq!(read.header.device_name, clip.header.device_name);
+        assert_eq!((read.header.width, read.header.height), (3, 2));
+        assert_eq!(
+            read.background
+                .map(|frame| frame.image_data.data().to_vec()),
+            Some(vec![1, 2, 3, 4, 5, 6])
+        );
+        assert_eq!(read.frames.len(), clip.frames.len());
+        for (read, frame) in read.frames.iter().zip(&clip.frames) {
+            assert_eq!(read.image_data.data(), frame.image_data.data());
+            assert_eq!(read.time_on, frame.time_on);
+            assert_eq!(read.last_ffc_time, frame.last_ffc_time);
+            assert_eq!(read.frame_temp_c, frame.frame_temp_c);
+            assert_eq!(read.last_ffc_temp_c, None);
+            assert!(!read.is_background_frame);
+        }
+    }
+
+    #[test]
+    fn rejects_shapes_that_overflow() {
+        for shape in [
+            [usize::MAX, 2, 2],
+            [0, usize::MAX, 2],
+            [2, 1 << (usize::BITS / 2), 1 << (usize::BITS / 2)],
+        ] {
+            let mut npz = Cursor::new(Vec::new());
+            let mut zip = ZipWriter::new(&mut npz);
+            zip.start_file("frames.npy", FileOptions::default())
+                .unwrap();
+            zip.write_all(&npy("<u2", &shape, &[0; 16])).unwrap();
+            zip.finish().unwrap();
+            drop(zip);
+            npz.set_position(0);
+            let error = read_npz(npz).expect_err("shape should be rejected");
+            assert_eq!(
+                error.kind(),
+                ErrorKind::InvalidData,
+                "{:?}: {}",
+                shape,
+                error
+            );
+        }
+    }
+}
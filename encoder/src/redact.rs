@@ -0,0 +1,75 @@
+use crate::metadata::rewrite_header;
+use cptv_shared::v2::types::Cptv2Header;
+use std::io;
+
+// Altitude is rounded to this many metres when coarsening a location.
+const ALTITUDE_GRID: f32 = 100.0;
+
+// Location fix times are rounded down to the day, in microseconds like the recording timestamp.
+const LOC_TIMESTAMP_GRID: u64 = 24 * 60 * 60 * 1_000_000;
+
+// Roughly the length of one degree of latitude.
+const METRES_PER_DEGREE: f32 = 111_320.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RedactOptions {
+    /// Size in degrees of the grid that latitude and longitude are snapped to, e.g. 0.01, or
+    /// `None` to remove the location altogether.
+    pub grid: Option<f32>,
+
+    /// Also remove the device name, device ID and camera serial number.
+    pub strip_device: bool,
+}
+
+impl Default for RedactOptions {
+    fn default() -> Self {
+        RedactOptions {
+            grid: Some(0.01),
+            strip_device: false,
+        }
+    }
+}
+
+/// Hides where a recording was made.
+///
+/// With a grid, latitude and longitude are rounded to the nearest grid point, altitude to the
+/// nearest 100m and the location timestamp down to the day, and the accuracy is widened to at
+/// least the grid size.  Without one, all of those fields are removed.
+pub fn redact_header(header: &mut Cptv2Header, options: &RedactOptions) {
+    match options.grid.filter(|grid| *grid > 0.0) {
+        Some(grid) => {
+            header.latitude = header.latitude.map(|latitude| snap(latitude, grid));
+            header.longitude = header.longitude.map(|longitude| snap(longitude, grid));
+            header.altitude = header.altitude.map(|altitude| snap(altitude, ALTITUDE_GRID));
+            header.loc_timestamp = header
+                .loc_timestamp
+                .map(|loc_timestamp| loc_timestamp - loc_timestamp % LOC_TIMESTAMP_GRID);
+            if header.latitude.is_some() || header.longitude.is_some() {
+                let grid_metres = grid * METRES_PER_DEGREE;
+                header.accuracy = Some(header.accuracy.unwrap_or(0.0).max(grid_metres));
+            }
+        }
+        None => {
+            header.latitude = None;
+            header.longitude = None;
+            header.altitude = None;
+            header.accuracy = None;
+            header.loc_timestamp = None;
+        }
+    }
+    if options.strip_device {
+        header.device_name = String::new();
+        header.device_id = None;
+        header.serial_number = None;
+    }
+}
+
+/// Redacts the header of a gzipped CPTV file, copying the frames through untouched.  Header
+/// fields this version doesn't know about are kept as they are, so they aren't redacted.
+pub fn redact(file: &[u8], options: &RedactOptions) -> io::Result<Vec<u8>> {
+    rewrite_header(file, |header| redact_header(header, options))
+}
+
+fn snap(value: f32, grid: f32) -> f32 {
+    (value / grid).round() * grid
+}
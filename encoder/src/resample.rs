@@ -0,0 +1,123 @@
+use cptv_shared::v2::types::{Clip, CptvFrame, FrameData};
+use std::io;
+use std::io::ErrorKind;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ResampleOptions {
+    /// Frame rate of the resampled clip.  Must be no higher than the original frame rate.
+    pub fps: u8,
+
+    /// Average each kept frame with the frames dropped after it, which reduces noise, rather
+    /// than just dropping them.
+    pub average: bool,
+}
+
+/// Reduces the frame rate of a clip.
+///
+/// Frames are picked by `time_on` rather than by index, so dropped frames in the original don't
+/// throw the timing out: the clip is split into periods of `1 / fps` seconds from the first
+/// frame, and each period with any frames in it becomes one frame of the result, with the
+/// `time_on` and other metadata of the first frame in the period.  The background frame, if any,
+/// is kept as it is.  The range of pixel values in the header is updated to match the frames that
+/// are left.
+pub fn resample_clip(mut clip: Clip, options: ResampleOptions) -> io::Result<Clip> {
+    if options.fps == 0 || options.fps > clip.header.fps {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Can't resample a {}fps clip to {}fps",
+                clip.header.fps, options.fps
+            ),
+        ));
+    }
+    let start = match clip.frames.first() {
+        Some(frame) => frame.time_on,
+        None => {
+            clip.header.fps = options.fps;
+            return Ok(clip);
+        }
+    };
+    let period_of = |frame: &CptvFrame| {
+        frame.time_on.saturating_sub(start) as u64 * options.fps as u64 / 1000
+    };
+
+    let mut frames: Vec<CptvFrame> = Vec::new();
+    let mut group: Vec<CptvFrame> = Vec::new();
+    for frame in clip.frames {
+        if group.last().is_some_and(|last| period_of(&frame) != period_of(last)) {
+            frames.push(combine(std::mem::take(&mut group), options.average));
+        }
+        group.push(frame);
+    }
+    if !group.is_empty() {
+        frames.push(combine(group, options.average));
+    }
+
+    clip.frames = frames;
+    clip.header.fps = options.fps;
+    clip.header.total_frame_count = None;
+    clip.update_value_range();
+    Ok(clip)
+}
+
+// The frame that stands in for a whole period of the original clip.
+fn combine(mut group: Vec<CptvFrame>, average: bool) -> CptvFrame {
+    if !average || group.len() == 1 {
+        return group.swap_remove(0);
+    }
+    let width = group[0].image_data.width();
+    let height = group[0].image_data.height();
+    let mut totals = vec![0u32; width * height];
+    for frame in &group {
+        for (total, value) in totals.iter_mut().zip(frame.image_data.data()) {
+            *total += *value as u32;
+        }
+    }
+    let count = group.len() as u32;
+    let averaged: Vec<u16> = totals
+        .iter()
+        .map(|total| ((total + count / 2) / count) as u16)
+        .collect();
+    let mut frame = group.swap_remove(0);
+    frame.image_data = FrameData::with_dimensions_and_data(width, height, &averaged);
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cptv_shared::v2::types::Cptv2Header;
+
+    #[test]
+    fn updates_the_range_of_pixel_values() {
+        let mut header = Cptv2Header::new();
+        header.width = 2;
+        header.height = 1;
+        header.fps = 9;
+        header.min_value = Some(1000);
+        header.max_value = Some(1300);
+        let frames = (0..3)
+            .map(|index| {
+                let mut frame = CptvFrame::new_with_dimensions(2, 1);
+                let pixels = [1000 + 100 * index, 1100 + 100 * index];
+                frame.image_data = FrameData::with_dimensions_and_data(2, 1, &pixels);
+                frame.time_on = 1000 + 111 * index as u32;
+                frame
+            })
+            .collect();
+        let clip = Clip {
+            header,
+            background: None,
+            frames,
+        };
+        let options = ResampleOptions {
+            fps: 3,
+            average: true,
+        };
+        let resampled = resample_clip(clip, options).unwrap();
+        assert_eq!(resampled.frames.len(), 1);
+        assert_eq!(resampled.frames[0].image_data.data(), [1100, 1200]);
+        assert_eq!(resampled.header.min_value, Some(1100));
+        assert_eq!(resampled.header.max_value, Some(1200));
+    }
+}
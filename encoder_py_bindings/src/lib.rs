@@ -2,6 +2,9 @@ use std::collections::HashMap;
 use pyo3::prelude::*;
 use pyo3::types::{PyBytes, PyTuple};
 use cptv_encoder::get_packed_frame_data;
+use cptv_shared::v2::calibration::Calibration;
+use cptv_shared::v2::motion::{MotionDetector, MotionParams};
+use cptv_shared::v2::types::FrameData;
 use cptv_shared::v2::types::Cptv2Header;
 
 
@@ -21,6 +24,46 @@ fn push_frame_data<'py> (py: Python<'py>, prev_frame: Option<&[u8]>, curr_frame:
     Ok(PyBytes::new(py, &output))
 }
 
+/// Converts raw frame data (native endian u16 pixels) to degrees Celsius, picking the conversion
+/// from the camera brand and model in the CPTV header.  Lepton 3.5 files are radiometric; for
+/// older sensors the result is an approximation anchored on `reference_temp_c`, which should be
+/// the sensor temperature at the last FFC (or the frame temperature).
+#[pyfunction]
+fn frame_to_celsius(frame: &[u8], brand: Option<String>, model: Option<String>, reference_temp_c: Option<f32>) -> PyResult<Vec<f32>> {
+    let mut header = Cptv2Header::new();
+    header.brand = brand;
+    header.model = model;
+    let calibration = Calibration::for_header(&header)
+        .ok_or_else(|| pyo3::exceptions::PyValueError::new_err("Unknown sensor, unable to calibrate"))?;
+    let pixels = unsafe { std::slice::from_raw_parts(frame.as_ptr() as *const u16, frame.len() / 2) };
+    Ok(pixels.iter().map(|value| calibration.pixel_to_celsius(*value, reference_temp_c)).collect())
+}
+
+/// Foreground masks and motion scores for a sequence of frames (native endian u16 pixels).
+/// `model` is "stored" (compare against `background`), "runningAverage" (adapting at `alpha`)
+/// or "median" (the per-pixel median of the frames).  `threshold` is in raw pixel units.
+/// Returns a mask of one byte per pixel (1 for foreground) and a score for each frame.
+#[pyfunction]
+fn motion_masks<'py>(py: Python<'py>, frames: Vec<&[u8]>, width: usize, height: usize, model: &str, background: Option<&[u8]>, threshold: f32, dilation: usize, alpha: f32) -> PyResult<Vec<(&'py PyBytes, f32)>> {
+    let to_frame_data = |bytes: &[u8]| {
+        let pixels = unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const u16, bytes.len() / 2) };
+        FrameData::with_dimensions_and_data(width, height, pixels)
+    };
+    let frames: Vec<FrameData> = frames.into_iter().map(to_frame_data).collect();
+    let params = MotionParams { threshold, dilation };
+    let detector = match model {
+        "stored" => background.map(|background| MotionDetector::with_background(&to_frame_data(background), params)),
+        "runningAverage" => frames.first().map(|first_frame| MotionDetector::with_running_average(first_frame, alpha, params)),
+        "median" => MotionDetector::with_clip_median(&frames.iter().collect::<Vec<_>>(), params),
+        _ => return Err(pyo3::exceptions::PyValueError::new_err(format!("Unknown background model {}", model))),
+    };
+    let mut detector = detector.ok_or_else(|| pyo3::exceptions::PyValueError::new_err("No background to compare frames against"))?;
+    Ok(frames.iter().map(|frame| {
+        let motion = detector.process(frame);
+        (PyBytes::new(py, motion.mask.data()), motion.score)
+    }).collect())
+}
+
 /// Formats the sum of two numbers as string.
 #[pyfunction]
 fn sum_as_string(a: usize, b: usize) -> PyResult<String> {
@@ -33,6 +76,8 @@ fn sum_as_string(a: usize, b: usize) -> PyResult<String> {
 #[pymodule]
 fn encoder_py_bindings(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(push_frame_data, m)?)?;
+    m.add_function(wrap_pyfunction!(frame_to_celsius, m)?)?;
+    m.add_function(wrap_pyfunction!(motion_masks, m)?)?;
 
     Ok(())
 }
@@ -37,6 +37,26 @@ declare class CptvDecoder {
      */
     getStreamMetadata(url: string): Promise<CptvHeader>;
 
+    /**
+     * Decode every frame of an already loaded byte array, and get statistics for the whole clip.
+     * @param fileBytes (Uint8Array)
+     * @returns The clip statistics, or an error string if the file couldn't be decoded
+     */
+    getBytesClipStats(fileBytes: Uint8Array): Promise<ClipStats | string | null>;
+
+    /**
+     * Gather statistics for `getClipStats` over the frames returned by `getNextFrame`.  Off by default, since it
+     * adds a little work per frame.  The setting persists across files.
+     * @param enabled (Boolean)
+     */
+    setCollectStats(enabled: boolean): Promise<boolean>;
+
+    /**
+     * Statistics over the frames returned by `getNextFrame` so far, including a recommended range to normalise
+     * frames to for display.  Null if `setCollectStats(true)` hasn't been called, or no frames have been read yet.
+     */
+    getClipStats(): Promise<ClipStats | null>;
+
     /**
      * If the file stream has completed, this gives the total number
      * of playable frames in the file (excluding any background frame).
@@ -50,10 +70,18 @@ declare class CptvDecoder {
     getHeader(): Promise<CptvHeader>;
 
     /**
-     * Get the next frame in the sequence, if there is one.
+     * Get the next frame in the sequence, if there is one.  The background frame is never returned here,
+     * see `getBackgroundFrame`.
      */
     getNextFrame(): Promise<CptvFrame | null>;
 
+    /**
+     * Get the background frame, if the file has one.  This can be called at any point before the stream has
+     * been consumed by `getTotalFrames` or one of the metadata functions, and doesn't change which frame
+     * `getNextFrame` returns next.
+     */
+    getBackgroundFrame(): Promise<CptvFrame | null>;
+
     /**
      * Stream load progress from 0..1
      */
@@ -80,6 +108,141 @@ declare class CptvDecoder {
      * Get any stream error message
      */
     getStreamError(): Promise<string | null>
+
+    /**
+     * Frame timing analysed against the declared frame rate, for the frames read so far.
+     */
+    getTimingReport(): Promise<TimingReport | null>;
+
+    /**
+     * The most recently returned frame converted to degrees Celsius, in the same pixel order as the frame data.
+     * Lepton 3.5 files are radiometric.  For older Lepton sensors (and files without a brand or model) this is only
+     * an approximation based on the sensor temperature at the last FFC, good to several degrees.  Null if the camera
+     * isn't one we know how to calibrate.
+     */
+    getFrameTemperatures(): Promise<Float32Array | null>;
+
+    /**
+     * Temperature statistics in degrees Celsius over a rectangular region of the most recently returned frame.
+     * The region is clipped to the frame.  Approximate for non-radiometric sensors, as for getFrameTemperatures.
+     * Null if the region is outside the frame, or the camera isn't one we know how to calibrate.
+     */
+    getRegionTemperatureStats(x: number, y: number, width: number, height: number): Promise<RegionStats | null>;
+
+    /**
+     * If the file was cut off partway through (e.g. an interrupted upload), the byte offset into the
+     * decompressed stream where the first incomplete frame starts.  All complete frames before that point
+     * are still returned.  Null if the stream isn't truncated, or hasn't been fully read yet.
+     */
+    getTruncatedAt(): Promise<number | null>;
+
+    /**
+     * When enabled, corrupted frames are skipped and decoding resumes at the next frame that can be decoded,
+     * rather than halting with a stream error.  Frames after a skip are decoded against the wrong frame, so their
+     * pixels are off by however much the scene changed over the skipped frames, and can be garbage.  They have
+     * `decodedAfterSkip` set in their header.  Decoding is exact again from the next intra frame, but only merged
+     * files have those.  Must be called before initialising the decoder with a file.
+     * @param enabled (Boolean)
+     */
+    setRecoveryMode(enabled: boolean): Promise<boolean>;
+
+    /**
+     * Ranges of the decompressed stream that were skipped over in recovery mode.
+     */
+    getSkippedRanges(): Promise<SkippedRange[]>;
+
+    /**
+     * Enables motion detection, so that frames returned by `getNextFrame` include a foreground mask that can be
+     * drawn as an overlay.  Pass null to disable it again.  Settings persist across files.
+     * @returns False if the background model isn't supported.
+     */
+    setMotionDetection(options: MotionDetectionOptions | null): Promise<boolean>;
+
+    /**
+     * Leave unreliable pixels (such as garbage edge rows and columns on some sensors) out of the min and max
+     * reported for each frame.  Each exclusion applies to a camera model, or to any other model if `model` is
+     * left out.  Settings persist across files, and replace any previously set.
+     */
+    setMinMaxExclusions(exclusions: MinMaxExclusion[]): Promise<boolean>;
+
+    /**
+     * Tracks of the moving regions found by motion detection in the frames read so far.  Null if motion detection
+     * isn't enabled.
+     */
+    getTracks(): Promise<Track[] | null>;
+}
+
+export interface EdgeBorder {
+    top: number;
+    right: number;
+    bottom: number;
+    left: number;
+}
+
+export interface MinMaxExclusion {
+    // Header model this applies to, e.g. "lepton3"
+    model?: string;
+    // Number of rows/columns to ignore at each edge
+    border?: number | Partial<EdgeBorder>;
+    // One byte per pixel in row order, where non-zero means ignore the pixel
+    mask?: Uint8Array;
+}
+
+export interface MotionDetectionOptions {
+    // Compare against the file's background frame, or a running average of the frames so far.  Defaults to "stored".
+    model?: "stored" | "runningAverage";
+    // How much warmer than the background a pixel must be to count as foreground, in raw pixel units.  Defaults to 50.
+    threshold?: number;
+    // Radius in pixels to grow the foreground by.  Defaults to 1.
+    dilation?: number;
+    // How quickly a running average background adapts, from 0..1.  Defaults to 0.05.
+    alpha?: number;
+}
+
+export interface FrameMotion {
+    // 1 for foreground pixels, 0 otherwise, in the same order as the frame data
+    mask: Uint8Array;
+    // Fraction of the frame that is foreground
+    score: number;
+    // Tracks present in this frame
+    tracks: FrameTrack[];
+}
+
+export interface BoundingBox {
+    x: number;
+    y: number;
+    width: number;
+    height: number;
+}
+
+export interface TrackPoint {
+    frameIndex: number;
+    bounds: BoundingBox;
+    centroid: [number, number];
+    // Number of pixels in the region
+    mass: number;
+    // Hottest raw pixel value in the region, and its [x, y] position
+    peakValue: number;
+    peakPosition: [number, number];
+    // Null if the camera model isn't one we know how to calibrate
+    peakTempC: number | null;
+}
+
+export interface Track {
+    id: number;
+    points: TrackPoint[];
+}
+
+export interface FrameTrack extends TrackPoint {
+    id: number;
+}
+
+export interface SkippedRange {
+    // Byte offset into the decompressed stream
+    offset: number;
+    length: number;
+    // Index of the first frame decoded after the skipped bytes
+    frameIndex: number;
 }
 
 export interface CptvHeader {
@@ -102,23 +265,87 @@ export interface CptvHeader {
     altitude: number | null;
     accuracy: number | null;
     hasBackgroundFrame: boolean;
-    // Duration in seconds, *including* any background frame.  This is for compatibility with current
-    // durations stored in DB which *include* background frames, the user may wish to subtract 1/fps seconds
-    // to get the actual duration.
+    // Duration in seconds, not including any background frame.
     // Only set if we used one of the getFileMetadata|getStreamMetadata, and scan the entire file.
     duration?: number;
     totalFrames?: number;
+    // Set if the file was cut off partway through, in which case duration and totalFrames only
+    // count the frames that could be recovered.
+    truncated?: boolean;
+    truncatedAt?: number;
 
     minValue?: number;
     maxValue?: number;
 }
 
+export interface FrameStats {
+    min: number;
+    max: number;
+    mean: number;
+    // Whether the frame was captured while the sensor was settling after an FFC
+    ffcSettling: boolean;
+}
+
+export interface ClipStats {
+    min: number;
+    max: number;
+    // Values at the 0.1 and 99.9 percentiles, over every pixel of every frame
+    lowPercentile: number;
+    highPercentile: number;
+    histogram: {
+        // Value at the start of the first bin
+        start: number;
+        binWidth: number;
+        counts: number[];
+    };
+    frames: FrameStats[];
+    // Recommended range to normalise frames to for display, leaving out outlying pixels, and frames settling after an FFC
+    displayMin: number;
+    displayMax: number;
+}
+
+export interface FrameGap {
+    // Index of the frame after the gap
+    frameIndex: number;
+    gapMs: number;
+    missingFrames: number;
+}
+
+export interface RegionStats {
+    min: number;
+    max: number;
+    mean: number;
+    // Number of pixels in the region
+    count: number;
+}
+
+export interface TimingReport {
+    expectedIntervalMs: number;
+    meanIntervalMs: number;
+    minIntervalMs: number;
+    maxIntervalMs: number;
+    // Standard deviation from the expected interval, over intervals with no dropped frames
+    jitterMs: number;
+    intervals: number;
+    droppedFrames: number;
+    gaps: FrameGap[];
+    // Frame indices (excluding any background frame)
+    duplicatedTimestamps: number[];
+    outOfOrder: number[];
+    irregularIntervals: number[];
+}
+
 export interface CptvFrameHeader {
     timeOnMs: number;
+    // Wall-clock time of the frame in milliseconds since the unix epoch
+    timestampMs: number | null;
     lastFfcTimeMs: number | null;
     lastFfcTempC: number | null;
     frameTempC: number | null;
     isBackgroundFrame: boolean;
+    // Set in recovery mode on frames after corrupted data was skipped, up to the next intra frame.
+    // Frames are delta encoded against the lost frame before them, so their pixels can be garbage.
+    decodedAfterSkip: boolean;
     imageData: {
         width: number;
         height: number;
@@ -130,6 +357,13 @@ export interface CptvFrameHeader {
          * Maximum value for this frame
          */
         max: number;
+        /**
+         * Set if some pixels were left out of the min and max
+         */
+        minMaxExclusion?: {
+            border: EdgeBorder;
+            maskedPixels: number;
+        };
     }
 }
 
@@ -143,5 +377,10 @@ export interface CptvFrame {
      * Frame header
      */
     meta: CptvFrameHeader;
+
+    /**
+     * Foreground mask, if motion detection is enabled and there's a background to compare against
+     */
+    motion?: FrameMotion;
 }
 
@@ -0,0 +1,192 @@
+use crate::v2::types::{Cptv2Header, CptvFrame, FrameData};
+use serde::Serialize;
+
+const CENTIKELVIN_OFFSET: f32 = 273.15;
+
+// The defaults for `Calibration::lepton_relative`.  These are typical values, not measured
+// ones: a non-radiometric Lepton is re-zeroed at each FFC so that the shutter (at the sensor
+// temperature) reads about the middle of its 14 bit range, and its gain varies from sensor to
+// sensor and with the sensor temperature.  Expect errors of several degrees.
+const LEPTON_REFERENCE_COUNT: u16 = 1 << 13;
+const LEPTON_DEGREES_PER_COUNT: f32 = 0.05;
+
+/// How raw pixel values map to temperatures for a given sensor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Calibration {
+    /// Pixel values are absolute temperatures in centikelvin, as reported by a Lepton 3.5 in
+    /// radiometric (TLinear) mode.
+    Centikelvin,
+
+    /// Pixel values are relative counts from a non-radiometric sensor.  Temperatures are an
+    /// approximation: `reference_count` is taken to be at the sensor temperature at the last
+    /// FFC (falling back to the frame temperature), and each count away from it adds
+    /// `degrees_per_count`.  Good enough to tell a warm animal from the background, not for
+    /// absolute measurements.
+    Relative {
+        reference_count: u16,
+        degrees_per_count: f32,
+    },
+}
+
+impl Calibration {
+    /// Picks a calibration from the header `brand` and `model`.  A Lepton 3.5 is radiometric;
+    /// other Leptons, and older files without a brand or model (which all come from Lepton 3
+    /// cameras), get the approximate `lepton_relative` conversion.  Returns `None` for sensors
+    /// we don't know how to calibrate.
+    pub fn for_header(header: &Cptv2Header) -> Option<Calibration> {
+        let brand = header.brand.as_deref().map(str::to_lowercase);
+        let model = header.model.as_deref().map(str::to_lowercase);
+        match (brand.as_deref(), model.as_deref()) {
+            (_, Some(model)) if model.contains("lepton3.5") || model.contains("lepton 3.5") => {
+                Some(Calibration::Centikelvin)
+            }
+            (_, Some(model)) if model.contains("lepton") => Some(Calibration::lepton_relative()),
+            (Some("flir"), _) | (None, None) => Some(Calibration::lepton_relative()),
+            _ => None,
+        }
+    }
+
+    /// The approximate conversion for non-radiometric Lepton sensors, using typical values.
+    /// Construct `Calibration::Relative` directly for a sensor that has been measured.
+    pub fn lepton_relative() -> Calibration {
+        Calibration::Relative {
+            reference_count: LEPTON_REFERENCE_COUNT,
+            degrees_per_count: LEPTON_DEGREES_PER_COUNT,
+        }
+    }
+
+    /// Whether temperatures from this calibration are only approximate.
+    pub fn is_approximate(&self) -> bool {
+        matches!(self, Calibration::Relative { .. })
+    }
+
+    /// Size of one raw pixel unit in °C.
+    pub fn degrees_per_count(&self) -> f32 {
+        match *self {
+            Calibration::Centikelvin => 0.01,
+            Calibration::Relative {
+                degrees_per_count, ..
+            } => degrees_per_count,
+        }
+    }
+
+    /// Converts a single raw pixel value.  `reference_temp_c` is the sensor temperature at
+    /// the last FFC; it's only used by relative calibrations, which give NaN without it.
+    pub fn pixel_to_celsius(&self, value: u16, reference_temp_c: Option<f32>) -> f32 {
+        match *self {
+            Calibration::Centikelvin => value as f32 / 100.0 - CENTIKELVIN_OFFSET,
+            Calibration::Relative {
+                reference_count,
+                degrees_per_count,
+            } => match reference_temp_c {
+                Some(reference_temp_c) => {
+                    reference_temp_c
+                        + (value as f32 - reference_count as f32) * degrees_per_count
+                }
+                None => f32::NAN,
+            },
+        }
+    }
+
+    pub fn frame_data_to_celsius(
+        &self,
+        frame_data: &FrameData,
+        reference_temp_c: Option<f32>,
+    ) -> TemperatureFrame {
+        TemperatureFrame {
+            data: frame_data
+                .data()
+                .iter()
+                .map(|value| self.pixel_to_celsius(*value, reference_temp_c))
+                .collect(),
+            width: frame_data.width(),
+            height: frame_data.height(),
+        }
+    }
+
+    /// Converts a frame, taking the reference temperature for relative calibrations from
+    /// the frame's `last_ffc_temp_c`, or its `frame_temp_c` if that's missing.
+    pub fn to_celsius(&self, frame: &CptvFrame) -> TemperatureFrame {
+        self.frame_data_to_celsius(&frame.image_data, reference_temp_c(frame))
+    }
+}
+
+/// The sensor temperature relative calibrations are anchored to.
+pub fn reference_temp_c(frame: &CptvFrame) -> Option<f32> {
+    frame.last_ffc_temp_c.or(frame.frame_temp_c)
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct RegionStats {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub count: usize,
+}
+
+/// A frame of temperatures in °C.
+#[derive(Debug, Clone)]
+pub struct TemperatureFrame {
+    data: Vec<f32>,
+    width: usize,
+    height: usize,
+}
+
+impl TemperatureFrame {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn data(&self) -> &[f32] {
+        &self.data
+    }
+
+    pub fn into_vec(self) -> Vec<f32> {
+        self.data
+    }
+
+    pub fn temperature_at(&self, x: usize, y: usize) -> Option<f32> {
+        if x < self.width && y < self.height {
+            Some(self.data[y * self.width + x])
+        } else {
+            None
+        }
+    }
+
+    /// Statistics over a rectangular region, clipped to the frame.  Returns `None` if the
+    /// region doesn't overlap the frame.
+    pub fn region_stats(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Option<RegionStats> {
+        let right = (x + width).min(self.width);
+        let bottom = (y + height).min(self.height);
+        if x >= right || y >= bottom {
+            return None;
+        }
+        let mut stats = RegionStats {
+            min: f32::MAX,
+            max: f32::MIN,
+            mean: 0.0,
+            count: 0,
+        };
+        let mut total = 0.0f64;
+        for row in self.data.chunks(self.width).take(bottom).skip(y) {
+            for temp in &row[x..right] {
+                stats.min = stats.min.min(*temp);
+                stats.max = stats.max.max(*temp);
+                total += *temp as f64;
+                stats.count += 1;
+            }
+        }
+        stats.mean = (total / stats.count as f64) as f32;
+        Some(stats)
+    }
+}
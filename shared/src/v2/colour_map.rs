@@ -0,0 +1,71 @@
+use crate::v2::types::FrameData;
+
+// Evenly spaced gradient stops for each colour map, from coldest to hottest.
+const GREYSCALE: [[u8; 3]; 2] = [[0, 0, 0], [255, 255, 255]];
+const IRONBOW: [[u8; 3]; 6] = [
+    [0, 0, 0],
+    [32, 0, 140],
+    [140, 0, 160],
+    [220, 50, 70],
+    [255, 190, 0],
+    [255, 255, 240],
+];
+const VIRIDIS: [[u8; 3]; 5] = [
+    [68, 1, 84],
+    [59, 82, 139],
+    [33, 145, 140],
+    [94, 201, 98],
+    [253, 231, 37],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColourMap {
+    Greyscale,
+    Ironbow,
+    Viridis,
+}
+
+impl ColourMap {
+    pub fn from_name(name: &str) -> Option<ColourMap> {
+        match name.to_lowercase().as_str() {
+            "greyscale" | "grayscale" => Some(ColourMap::Greyscale),
+            "ironbow" => Some(ColourMap::Ironbow),
+            "viridis" => Some(ColourMap::Viridis),
+            _ => None,
+        }
+    }
+
+    fn stops(&self) -> &'static [[u8; 3]] {
+        match self {
+            ColourMap::Greyscale => &GREYSCALE,
+            ColourMap::Ironbow => &IRONBOW,
+            ColourMap::Viridis => &VIRIDIS,
+        }
+    }
+
+    /// The colour for `position`, where 0 is the coldest end of the map and 1 the hottest.
+    pub fn colour(&self, position: f32) -> [u8; 3] {
+        let stops = self.stops();
+        let position = position.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+        let index = (position as usize).min(stops.len() - 2);
+        let fraction = position - index as f32;
+        let (from, to) = (stops[index], stops[index + 1]);
+        let mut colour = [0u8; 3];
+        for channel in 0..3 {
+            colour[channel] = (from[channel] as f32
+                + (to[channel] as f32 - from[channel] as f32) * fraction)
+                .round() as u8;
+        }
+        colour
+    }
+
+    /// Renders a frame to 8 bit RGB, mapping `min..=max` onto the full colour map.
+    pub fn render(&self, frame: &FrameData, min: u16, max: u16) -> Vec<u8> {
+        let range = (max.saturating_sub(min)).max(1) as f32;
+        frame
+            .data()
+            .iter()
+            .flat_map(|value| self.colour(value.saturating_sub(min) as f32 / range))
+            .collect()
+    }
+}
@@ -0,0 +1,71 @@
+use crate::v2::types::CptvFrame;
+use serde::Serialize;
+
+/// A flat field correction (shutter) event that happened during a clip.
+#[derive(Serialize, Debug, Clone)]
+pub struct FfcEvent {
+    // Index of the first frame captured after the FFC.
+    #[serde(rename = "frameIndex")]
+    pub frame_index: usize,
+
+    // When the FFC happened, in milliseconds since the camera powered on (same as time_on).
+    #[serde(rename = "timeOnMs")]
+    pub time_on: u32,
+    #[serde(rename = "tempC")]
+    pub temp_c: Option<f32>,
+
+    // Change in sensor temperature since the previous FFC.
+    #[serde(rename = "tempDeltaC")]
+    pub temp_delta_c: Option<f32>,
+}
+
+/// Iterator over the FFC events in a sequence of frames.  Only FFCs that happen during the clip
+/// are reported; the FFC in effect when the clip starts is used as the baseline for the first
+/// temperature delta.
+pub struct FfcEvents<'a, I: Iterator<Item = &'a CptvFrame>> {
+    frames: std::iter::Enumerate<I>,
+    last_ffc_time: Option<u32>,
+    last_ffc_temp_c: Option<f32>,
+    started: bool,
+}
+
+impl<'a, I: Iterator<Item = &'a CptvFrame>> Iterator for FfcEvents<'a, I> {
+    type Item = FfcEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for (frame_index, frame) in self.frames.by_ref() {
+            if frame.is_background_frame || frame.last_ffc_time.is_none() {
+                continue;
+            }
+            let is_new_ffc = self.started && frame.last_ffc_time != self.last_ffc_time;
+            let prev_temp_c = self.last_ffc_temp_c;
+            self.started = true;
+            self.last_ffc_time = frame.last_ffc_time;
+            self.last_ffc_temp_c = frame.last_ffc_temp_c;
+            if is_new_ffc {
+                return Some(FfcEvent {
+                    frame_index,
+                    time_on: frame.last_ffc_time.unwrap(),
+                    temp_c: frame.last_ffc_temp_c,
+                    temp_delta_c: match (frame.last_ffc_temp_c, prev_temp_c) {
+                        (Some(temp_c), Some(prev_temp_c)) => Some(temp_c - prev_temp_c),
+                        _ => None,
+                    },
+                });
+            }
+        }
+        None
+    }
+}
+
+/// FFC events for a clip.  Frame indices count every frame given, including any background frame.
+pub fn ffc_events<'a, I: IntoIterator<Item = &'a CptvFrame>>(
+    frames: I,
+) -> FfcEvents<'a, I::IntoIter> {
+    FfcEvents {
+        frames: frames.into_iter().enumerate(),
+        last_ffc_time: None,
+        last_ffc_temp_c: None,
+        started: false,
+    }
+}
@@ -0,0 +1,336 @@
+use crate::v2::types::{Clip, Cptv2Header, CptvFrame, FrameData};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize, Serializer};
+use std::convert::{TryFrom, TryInto};
+
+/// How pixels are written out in the JSON form of a clip.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PixelFormat {
+    /// An array of rows, each an array of raw pixel values.  Easy to read, edit and diff.
+    Rows,
+
+    /// The pixels in row order as little endian u16s, base64 encoded.  Much more compact.
+    Base64,
+}
+
+/// Pixel data as it appears in JSON: a base64 string, an array of rows, or a flat array of
+/// `width * height` values in row order.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Pixels {
+    Base64(String),
+    Rows(Vec<Vec<u16>>),
+    Flat(Vec<u16>),
+}
+
+impl Pixels {
+    pub fn new(frame: &FrameData, format: PixelFormat) -> Pixels {
+        match format {
+            PixelFormat::Rows => Pixels::Rows(
+                frame
+                    .data()
+                    .chunks(frame.width().max(1))
+                    .map(|row| row.to_vec())
+                    .collect(),
+            ),
+            PixelFormat::Base64 => {
+                let bytes: Vec<u8> = frame
+                    .data()
+                    .iter()
+                    .flat_map(|value| value.to_le_bytes())
+                    .collect();
+                Pixels::Base64(STANDARD.encode(bytes))
+            }
+        }
+    }
+
+    pub fn into_vec(self) -> Result<Vec<u16>, String> {
+        match self {
+            Pixels::Base64(encoded) => {
+                let bytes = STANDARD
+                    .decode(encoded)
+                    .map_err(|e| format!("Invalid base64 pixel data: {}", e))?;
+                if bytes.len() % 2 != 0 {
+                    return Err("Base64 pixel data has an odd number of bytes".to_string());
+                }
+                Ok(bytes
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                    .collect())
+            }
+            Pixels::Rows(rows) => Ok(rows.concat()),
+            Pixels::Flat(values) => Ok(values),
+        }
+    }
+}
+
+/// The JSON form of a clip, for fixtures that can be edited by hand and diffed:
+///
+/// ```json
+/// {
+///   "header": { "timestamp": 1600000000000000, "width": 160, "height": 120, ... },
+///   "background": { ...frame... },
+///   "frames": [
+///     {
+///       "timeOnMs": 100111,
+///       "lastFfcTimeMs": 95000,
+///       "lastFfcTempC": 24.1,
+///       "frameTempC": 24.3,
+///       "isBackgroundFrame": false,
+///       "imageData": { "width": 160, "height": 120, "data": [[...], ...] }
+///     }
+///   ]
+/// }
+/// ```
+///
+/// The header uses the same fields as `Cptv2Header` serializes to, and frames the same as
+/// `CptvFrame` plus the pixels in `imageData.data`, in any of the forms `Pixels` accepts.
+/// Optional frame fields that aren't set are left out, and `background` is only present if the
+/// clip has a background frame.  Frame min and max values are worked out from the pixels, so
+/// they aren't included.
+///
+/// `Clip` deserializes from this, and serializes to it with the pixels as rows; use
+/// `ClipJson::new` to pick the pixel format.  Either way it reads back as an identical `Clip`.
+#[derive(Serialize, Deserialize)]
+pub struct ClipJson {
+    header: Cptv2Header,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    background: Option<FrameJson>,
+    frames: Vec<FrameJson>,
+}
+
+impl ClipJson {
+    pub fn new(clip: &Clip, format: PixelFormat) -> ClipJson {
+        ClipJson {
+            header: clip.header.clone(),
+            background: clip
+                .background
+                .as_ref()
+                .map(|frame| FrameJson::new(frame, format)),
+            frames: clip
+                .frames
+                .iter()
+                .map(|frame| FrameJson::new(frame, format))
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<ClipJson> for Clip {
+    type Error = String;
+
+    fn try_from(json: ClipJson) -> Result<Self, Self::Error> {
+        Ok(Clip {
+            header: json.header,
+            background: json.background.map(CptvFrame::try_from).transpose()?,
+            frames: json
+                .frames
+                .into_iter()
+                .map(CptvFrame::try_from)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl Serialize for Clip {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ClipJson::new(self, PixelFormat::Rows).serialize(serializer)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct FrameJson {
+    #[serde(rename = "timeOnMs")]
+    time_on: u32,
+    #[serde(rename = "lastFfcTimeMs", skip_serializing_if = "Option::is_none")]
+    last_ffc_time: Option<u32>,
+    #[serde(rename = "lastFfcTempC", skip_serializing_if = "Option::is_none")]
+    last_ffc_temp_c: Option<f32>,
+    #[serde(rename = "frameTempC", skip_serializing_if = "Option::is_none")]
+    frame_temp_c: Option<f32>,
+    #[serde(rename = "isBackgroundFrame", default)]
+    is_background_frame: bool,
+    #[serde(rename = "decodedAfterSkip", default, skip_serializing_if = "is_false")]
+    decoded_after_skip: bool,
+    #[serde(rename = "imageData")]
+    image_data: FrameDataJson,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+impl FrameJson {
+    fn new(frame: &CptvFrame, format: PixelFormat) -> FrameJson {
+        FrameJson {
+            time_on: frame.time_on,
+            last_ffc_time: frame.last_ffc_time,
+            last_ffc_temp_c: frame.last_ffc_temp_c,
+            frame_temp_c: frame.frame_temp_c,
+            is_background_frame: frame.is_background_frame,
+            decoded_after_skip: frame.decoded_after_skip,
+            image_data: FrameDataJson {
+                width: frame.image_data.width(),
+                height: frame.image_data.height(),
+                data: Pixels::new(&frame.image_data, format),
+            },
+        }
+    }
+}
+
+impl TryFrom<FrameJson> for CptvFrame {
+    type Error = String;
+
+    fn try_from(json: FrameJson) -> Result<Self, Self::Error> {
+        let image_data: FrameData = json.image_data.try_into()?;
+        let mut frame = CptvFrame::new_with_dimensions(image_data.width(), image_data.height());
+        frame.time_on = json.time_on;
+        frame.last_ffc_time = json.last_ffc_time;
+        frame.last_ffc_temp_c = json.last_ffc_temp_c;
+        frame.frame_temp_c = json.frame_temp_c;
+        frame.is_background_frame = json.is_background_frame;
+        frame.decoded_after_skip = json.decoded_after_skip;
+        frame.image_data = image_data;
+        Ok(frame)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct FrameDataJson {
+    width: usize,
+    height: usize,
+    data: Pixels,
+}
+
+impl TryFrom<FrameDataJson> for FrameData {
+    type Error = String;
+
+    fn try_from(json: FrameDataJson) -> Result<Self, Self::Error> {
+        if let Pixels::Rows(rows) = &json.data {
+            if let Some(y) = rows.iter().position(|row| row.len() != json.width) {
+                return Err(format!(
+                    "Row {} has {} pixels, but the frame is {} wide",
+                    y,
+                    rows[y].len(),
+                    json.width
+                ));
+            }
+        }
+        let data = json.data.into_vec()?;
+        if data.len() != json.width * json.height {
+            return Err(format!(
+                "Expected {}x{} = {} pixels, but found {}",
+                json.width,
+                json.height,
+                json.width * json.height,
+                data.len()
+            ));
+        }
+        Ok(FrameData::with_dimensions_and_data(
+            json.width,
+            json.height,
+            &data,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(time_on: u32, width: usize, height: usize) -> CptvFrame {
+        let data: Vec<u16> = (0..width * height)
+            .map(|i| (i as u32 * 2311 + time_on) as u16)
+            .collect();
+        let mut frame = CptvFrame::new_with_dimensions(width, height);
+        frame.time_on = time_on;
+        frame.image_data = FrameData::with_dimensions_and_data(width, height, &data);
+        frame
+    }
+
+    fn clip() -> Clip {
+        let (width, height) = (5, 3);
+        let mut header = Cptv2Header::new();
+        header.width = width as u32;
+        header.height = height as u32;
+        header.fps = 9;
+        header.model = Some("lepton3.5".to_string());
+        header.has_background_frame = true;
+        let mut background = frame(0, width, height);
+        background.is_background_frame = true;
+        let mut first = frame(1000, width, height);
+        first.last_ffc_time = Some(500);
+        first.last_ffc_temp_c = Some(24.5);
+        first.frame_temp_c = Some(25.25);
+        let mut second = frame(1111, width, height);
+        second.decoded_after_skip = true;
+        let mut data = second.image_data.data().to_vec();
+        data[0] = u16::MAX;
+        second.image_data = FrameData::with_dimensions_and_data(width, height, &data);
+        Clip {
+            header,
+            background: Some(background),
+            frames: vec![first, second],
+        }
+    }
+
+    fn assert_same_frame(expected: &CptvFrame, actual: &CptvFrame) {
+        assert_eq!(expected.time_on, actual.time_on);
+        assert_eq!(expected.last_ffc_time, actual.last_ffc_time);
+        assert_eq!(expected.last_ffc_temp_c, actual.last_ffc_temp_c);
+        assert_eq!(expected.frame_temp_c, actual.frame_temp_c);
+        assert_eq!(expected.is_background_frame, actual.is_background_frame);
+        assert_eq!(expected.decoded_after_skip, actual.decoded_after_skip);
+        assert_eq!(expected.image_data.width(), actual.image_data.width());
+        assert_eq!(expected.image_data.height(), actual.image_data.height());
+        assert_eq!(expected.image_data.data(), actual.image_data.data());
+        assert_eq!(expected.image_data.min(), actual.image_data.min());
+        assert_eq!(expected.image_data.max(), actual.image_data.max());
+    }
+
+    fn assert_same_clip(expected: &Clip, actual: &Clip) {
+        assert_eq!(
+            serde_json::to_value(&expected.header).unwrap(),
+            serde_json::to_value(&actual.header).unwrap()
+        );
+        assert_eq!(expected.background.is_some(), actual.background.is_some());
+        if let (Some(expected), Some(actual)) = (&expected.background, &actual.background) {
+            assert_same_frame(expected, actual);
+        }
+        assert_eq!(expected.frames.len(), actual.frames.len());
+        for (expected, actual) in expected.frames.iter().zip(&actual.frames) {
+            assert_same_frame(expected, actual);
+        }
+    }
+
+    #[test]
+    fn round_trips_in_each_pixel_format() {
+        let clip = clip();
+        for format in [PixelFormat::Rows, PixelFormat::Base64] {
+            let json = serde_json::to_string(&ClipJson::new(&clip, format)).unwrap();
+            let read: Clip = serde_json::from_str(&json).unwrap();
+            assert_same_clip(&clip, &read);
+        }
+    }
+
+    #[test]
+    fn clips_serialize_to_the_json_form() {
+        let clip = clip();
+        let json = serde_json::to_string(&clip).unwrap();
+        assert_eq!(
+            json,
+            serde_json::to_string(&ClipJson::new(&clip, PixelFormat::Rows)).unwrap()
+        );
+        let read: Clip = serde_json::from_str(&json).unwrap();
+        assert_same_clip(&clip, &read);
+        assert_eq!(json, serde_json::to_string(&read).unwrap());
+    }
+
+    #[test]
+    fn rejects_pixels_that_dont_fit_the_frame() {
+        let json = r#"{"timeOnMs": 0, "imageData": {"width": 2, "height": 2, "data": [1, 2, 3]}}"#;
+        let frame: FrameJson = serde_json::from_str(json).unwrap();
+        assert!(CptvFrame::try_from(frame).is_err());
+    }
+}
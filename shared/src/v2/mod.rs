@@ -1,10 +1,19 @@
+pub mod calibration;
+pub mod colour_map;
+pub mod ffc;
+pub mod json;
+pub mod motion;
+pub mod stats;
+pub mod timing;
+pub mod tracking;
 pub mod types;
 #[allow(unused)]
 use log::{info, trace, warn};
 use nom::bytes::streaming::take;
 use nom::character::streaming::char;
+use nom::error::ErrorKind;
 use nom::number::streaming::{le_f32, le_u32, le_u64, le_u8, le_u16};
-use types::{Cptv2Header, CptvFrame, FieldType};
+use types::{Cptv2Header, CptvFrame, FieldType, SpatialPredictor};
 use crate::CptvHeader;
 
 // TODO(jon): Move most of this to cptv_common.  cptv_common might end up having
@@ -71,7 +80,7 @@ pub fn decode_cptv2_header(i: &[u8]) -> nom::IResult<&[u8], CptvHeader> {
                 meta.loc_timestamp = Some(le_u64(val)?.1);
             }
             FieldType::Altitude => {
-                meta.altitude = Some(le_f32(i)?.1);
+                meta.altitude = Some(le_f32(val)?.1);
             }
             FieldType::Accuracy => {
                 meta.accuracy = Some(le_f32(val)?.1);
@@ -102,15 +111,25 @@ pub fn decode_frame_header_v2(
     data: &[u8],
     width: usize,
     height: usize,
-    debug: bool,
+    _debug: bool,
 ) -> nom::IResult<&[u8], (&[u8], CptvFrame)> {
+    let (outer, frame) = parse_frame_header_v2(data, width, height)?;
+    if frame.frame_size == 0 {
+        return Err(nom::Err::Failure((outer, ErrorKind::Verify)));
+    }
+    let (i, data) = take(frame.frame_size as usize)(outer)?;
+    Ok((i, (data, frame)))
+}
+
+/// Parses just the frame header fields, leaving the packed frame data that follows.
+pub fn parse_frame_header_v2(
+    data: &[u8],
+    width: usize,
+    height: usize,
+) -> nom::IResult<&[u8], CptvFrame> {
     let (i, val) = take(1usize)(data)?;
     let (_, _) = char('F')(val)?;
     let (i, num_frame_fields) = le_u8(i)?;
-    // if debug {
-    //     warn!("Num frame fields {}", num_frame_fields);
-    // }
-
     let mut frame = CptvFrame::new_with_dimensions(width, height);
     let mut outer = i;
     for _ in 0..num_frame_fields as usize {
@@ -148,17 +167,23 @@ pub fn decode_frame_header_v2(
             FieldType::BackgroundFrame => {
                 frame.is_background_frame = le_u8(val)?.1 == 1;
             }
+            FieldType::Predictor => {
+                // A predictor we don't know means we can't decode the frame at all.
+                frame.predictor = SpatialPredictor::from_u8(le_u8(val)?.1)
+                    .ok_or(nom::Err::Failure((val, ErrorKind::Verify)))?;
+            }
+            FieldType::IntraFrame => {
+                frame.is_intra_frame = le_u8(val)?.1 == 1;
+            }
             _ => {
                 warn!(
                     "Unknown frame field type '{}', length: {}",
-                    field_code as char, field_length
+                    field_code, field_length
                 );
             }
         }
     }
-    assert!(frame.frame_size > 0);
-    let (i, data) = take(frame.frame_size as usize)(outer)?;
-    Ok((i, (data, frame)))
+    Ok((outer, frame))
 }
 
 fn decode_image_data_v2(
@@ -168,14 +193,18 @@ fn decode_image_data_v2(
     height: usize,
     frame: &mut CptvFrame,
     prev_frame: &Option<CptvFrame>,
-) {
+) -> bool {
+    if frame.predictor == SpatialPredictor::Med {
+        return decode_med_image_data_v2(i, current_px, width, height, frame, prev_frame);
+    }
     match prev_frame {
         Some(prev_frame) => {
             let prev_px = prev_frame.image_data[0][0] as i32;
             // Seed the initial pixel value
-            assert!(prev_px + current_px <= u16::MAX as i32);
-            assert!(prev_px + current_px >= 0);
-            frame.image_data[0][0] = (prev_px + current_px) as u16;
+            if prev_px + current_px > u16::MAX as i32 || prev_px + current_px < 0 {
+                return false;
+            }
+            frame.image_data.set(0, 0, (prev_px + current_px) as u16);
             for (index, delta) in BitUnpacker::new(i, frame.bit_width)
                 .take((width * height) - 1)
                 .enumerate()
@@ -189,8 +218,9 @@ fn decode_image_data_v2(
                 current_px += delta;
                 let prev_px = prev_frame.image_data[y][x] as i32;
 
-                assert!(prev_px + current_px <= u16::MAX as i32);
-                assert!(prev_px + current_px >= 0);
+                if prev_px + current_px > u16::MAX as i32 || prev_px + current_px < 0 {
+                    return false;
+                }
                 let px = (prev_px + current_px) as u16;
 
                 // This keeps track of min/max.
@@ -200,7 +230,10 @@ fn decode_image_data_v2(
         }
         None => {
             // This is the first frame, so we don't need to use a previous frame
-            frame.image_data[0][0] = current_px as u16;
+            if current_px > u16::MAX as i32 || current_px < 0 {
+                return false;
+            }
+            frame.image_data.set(0, 0, current_px as u16);
             for (index, delta) in BitUnpacker::new(i, frame.bit_width)
                 .take((width * height) - 1)
                 .enumerate()
@@ -210,6 +243,9 @@ fn decode_image_data_v2(
                 let x = index % width;
                 let x = if y & 1 == 1 { width - x - 1 } else { x };
                 current_px += delta;
+                if current_px > u16::MAX as i32 || current_px < 0 {
+                    return false;
+                }
                 let px = current_px as u16;
 
                 // This keeps track of min/max.
@@ -218,9 +254,88 @@ fn decode_image_data_v2(
             }
         }
     }
+    true
+}
+
+// Values are stored in row order as the difference from the MED prediction, so each one is
+// recovered from the neighbours already decoded.
+fn decode_med_image_data_v2(
+    i: &[u8],
+    first_value: i32,
+    width: usize,
+    height: usize,
+    frame: &mut CptvFrame,
+    prev_frame: &Option<CptvFrame>,
+) -> bool {
+    let mut values = vec![0i32; width * height];
+    let residuals = std::iter::once(first_value)
+        .chain(BitUnpacker::new(i, frame.bit_width).take((width * height) - 1));
+    for (index, residual) in residuals.enumerate() {
+        let value = residual.saturating_add(med_prediction(|n| values[n], index, width));
+        let prev_px = match prev_frame {
+            Some(prev_frame) => prev_frame.image_data.data()[index] as i32,
+            None => 0,
+        };
+        let px = prev_px + value;
+        if px > u16::MAX as i32 || px < 0 {
+            return false;
+        }
+        values[index] = value;
+
+        // This keeps track of min/max.
+        frame.image_data.set(index % width, index / width, px as u16);
+    }
+    true
+}
+
+/// The median edge detector prediction for the value at `index`, given the values before it in
+/// row order.  The first row is predicted from the left, the first column from above, and the
+/// first value from zero.
+#[inline(always)]
+pub fn med_prediction<F: Fn(usize) -> i32>(value: F, index: usize, width: usize) -> i32 {
+    match (index % width, index / width) {
+        (0, 0) => 0,
+        (_, 0) => value(index - 1),
+        (0, _) => value(index - width),
+        _ => {
+            let left = value(index - 1);
+            let above = value(index - width);
+            let above_left = value(index - width - 1);
+            if above_left >= left.max(above) {
+                left.min(above)
+            } else if above_left <= left.min(above) {
+                left.max(above)
+            } else {
+                left + above - above_left
+            }
+        }
+    }
 }
 
 pub fn unpack_frame_v2(prev_frame: &Option<CptvFrame>, data: &[u8], frame: &mut CptvFrame) {
+    assert!(
+        try_unpack_frame_v2(prev_frame, data, frame),
+        "Frame data decoded to pixel values outside of u16 range"
+    );
+}
+
+/// Like `unpack_frame_v2`, but returns false rather than panicking when the frame data is
+/// corrupt and decodes to pixel values that are out of range.  `prev_frame` is ignored for
+/// intra frames.
+pub fn try_unpack_frame_v2(
+    prev_frame: &Option<CptvFrame>,
+    data: &[u8],
+    frame: &mut CptvFrame,
+) -> bool {
+    if data.len() < 4 || frame.bit_width == 0 || frame.bit_width > MAX_BIT_WIDTH {
+        return false;
+    }
+    let no_prev_frame = None;
+    let prev_frame = if frame.is_intra_frame {
+        &no_prev_frame
+    } else {
+        prev_frame
+    };
     let initial_px = {
         let mut accum: i32 = 0;
         accum |= (data[3] as i32) << 24;
@@ -236,7 +351,76 @@ pub fn unpack_frame_v2(prev_frame: &Option<CptvFrame>, data: &[u8], frame: &mut
         frame.image_data.height(),
         frame,
         prev_frame,
-    );
+    )
+}
+
+/// The widest deltas `BitUnpacker` can read: it buffers bits in a u32 a byte at a time, so a
+/// wider delta would overflow the buffer.  Encoders pack to 8 or 16 bits in practice.
+pub const MAX_BIT_WIDTH: u8 = 24;
+
+/// Size in bytes of a packed frame: the first pixel is stored as a literal u32, followed by
+/// the remaining deltas packed at `bit_width` bits per pixel.
+pub fn expected_frame_size(bit_width: u8, width: usize, height: usize) -> usize {
+    4 + (bit_width as usize * (width * height - 1)).div_ceil(8)
+}
+
+// Frames written so far have at most 8 header fields, so anything claiming many more than
+// that is almost certainly not a frame header.
+const MAX_FRAME_HEADER_FIELDS: u8 = 16;
+
+/// Checks whether `data` starts with something that looks like a genuine frame header, rather
+/// than an 'F' byte that happens to occur inside corrupted data.  The field count must be sane,
+/// every field must be a known frame field of the right length, and the frame size must match
+/// what the bit width and dimensions require.
+pub fn is_plausible_frame_header_v2(data: &[u8], width: usize, height: usize) -> bool {
+    if data.first() != Some(&b'F') {
+        return false;
+    }
+    let num_frame_fields = match data.get(1) {
+        Some(&num) if num != 0 && num <= MAX_FRAME_HEADER_FIELDS => num,
+        _ => return false,
+    };
+    let mut offset = 2;
+    for _ in 0..num_frame_fields {
+        let (field_length, field_code) = match (data.get(offset), data.get(offset + 1)) {
+            (Some(&length), Some(&code)) => (length, code),
+            _ => return false,
+        };
+        let expected_length = match FieldType::from(field_code as char) {
+            FieldType::TimeOn
+            | FieldType::FrameSize
+            | FieldType::LastFfcTime
+            | FieldType::LastFfcTempC
+            | FieldType::FrameTempC => 4,
+            FieldType::BitsPerPixel
+            | FieldType::BackgroundFrame
+            | FieldType::Predictor
+            | FieldType::IntraFrame => 1,
+            _ => return false,
+        };
+        if field_length != expected_length {
+            return false;
+        }
+        offset += 2 + field_length as usize;
+    }
+    match parse_frame_header_v2(data, width, height) {
+        Ok((_, frame)) => {
+            frame.bit_width != 0
+                && frame.bit_width <= MAX_BIT_WIDTH
+                && frame.frame_size as usize
+                    == expected_frame_size(frame.bit_width, width, height)
+        }
+        Err(_) => false,
+    }
+}
+
+/// Scans forward for the next plausible frame header, returning its offset into `data`.
+pub fn find_next_frame_header_v2(data: &[u8], width: usize, height: usize) -> Option<usize> {
+    data.iter()
+        .enumerate()
+        .filter(|(_, byte)| **byte == b'F')
+        .map(|(offset, _)| offset)
+        .find(|offset| is_plausible_frame_header_v2(&data[*offset..], width, height))
 }
 
 #[inline(always)]
@@ -257,7 +441,7 @@ pub struct BitUnpacker<'a> {
 }
 
 impl<'a> BitUnpacker<'a> {
-    pub fn new(input: &'a [u8], bit_width: u8) -> BitUnpacker {
+    pub fn new(input: &'a [u8], bit_width: u8) -> BitUnpacker<'a> {
         BitUnpacker {
             input,
             offset: 0,
@@ -278,7 +462,7 @@ impl<'a> Iterator for BitUnpacker<'a> {
         while self.num_bits < self.bit_width {
             match self.input.get(self.offset) {
                 Some(byte) => {
-                    self.bits |= (*byte as u32) << ((24 - self.num_bits) as u8) as u32;
+                    self.bits |= (*byte as u32) << (24 - self.num_bits) as u32;
                     self.num_bits += 8;
                 }
                 None => return None,
@@ -287,7 +471,7 @@ impl<'a> Iterator for BitUnpacker<'a> {
         }
         let out =
             reverse_twos_complement(self.bits >> (32 - self.bit_width) as u32, self.bit_width);
-        self.bits = self.bits << self.bit_width as u32;
+        self.bits <<= self.bit_width as u32;
         self.num_bits -= self.bit_width;
         Some(out)
     }
@@ -0,0 +1,257 @@
+use crate::v2::calibration::Calibration;
+use crate::v2::types::{CptvFrame, FrameData};
+
+/// Where the background that frames are compared against comes from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BackgroundModel {
+    /// The background frame stored in the file.
+    Stored,
+
+    /// An exponential running average of the frames seen so far, where each frame contributes
+    /// `alpha` of the new background.  Foreground pixels don't contribute, so an animal that
+    /// stops moving isn't absorbed into the background straight away.
+    RunningAverage { alpha: f32 },
+
+    /// The per-pixel median of the whole clip.  Needs every frame up front.
+    Median,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MotionParams {
+    /// How much warmer than the background a pixel must be to count as foreground, in raw
+    /// pixel units.
+    pub threshold: f32,
+
+    /// Radius in pixels to grow the foreground mask by, to join up the parts of an animal.
+    pub dilation: usize,
+}
+
+impl MotionParams {
+    pub fn with_threshold_c(calibration: &Calibration, threshold_c: f32, dilation: usize) -> Self {
+        MotionParams {
+            threshold: threshold_c / calibration.degrees_per_count(),
+            dilation,
+        }
+    }
+}
+
+impl Default for MotionParams {
+    fn default() -> Self {
+        MotionParams {
+            threshold: 50.0,
+            dilation: 1,
+        }
+    }
+}
+
+/// Foreground pixels of a frame, in the same pixel order as `FrameData`.  Each pixel is 1 for
+/// foreground and 0 for background, so the mask can be used directly as an overlay.
+#[derive(Debug, Clone)]
+pub struct ForegroundMask {
+    data: Vec<u8>,
+    width: usize,
+    height: usize,
+}
+
+impl ForegroundMask {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn is_foreground(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.data[y * self.width + x] != 0
+    }
+
+    pub fn count(&self) -> usize {
+        self.data.iter().filter(|pixel| **pixel != 0).count()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MotionFrame {
+    pub mask: ForegroundMask,
+
+    /// Fraction of the frame (0..1) over the threshold, before dilation.
+    pub score: f32,
+}
+
+/// Compares frames against a background model to find the warm things that moved.
+#[derive(Debug, Clone)]
+pub struct MotionDetector {
+    background: Vec<f32>,
+    width: usize,
+    height: usize,
+    params: MotionParams,
+    // Only set for a running average background.
+    alpha: Option<f32>,
+}
+
+impl MotionDetector {
+    pub fn with_background(background: &FrameData, params: MotionParams) -> MotionDetector {
+        MotionDetector {
+            background: background.data().iter().map(|value| *value as f32).collect(),
+            width: background.width(),
+            height: background.height(),
+            params,
+            alpha: None,
+        }
+    }
+
+    /// A running average background, starting from `first_frame`.
+    pub fn with_running_average(
+        first_frame: &FrameData,
+        alpha: f32,
+        params: MotionParams,
+    ) -> MotionDetector {
+        MotionDetector {
+            alpha: Some(alpha.clamp(0.0, 1.0)),
+            ..MotionDetector::with_background(first_frame, params)
+        }
+    }
+
+    /// The per-pixel median of `frames`, or `None` if there are no frames.
+    pub fn with_clip_median(frames: &[&FrameData], params: MotionParams) -> Option<MotionDetector> {
+        let first_frame = frames.first()?;
+        let mut values = Vec::with_capacity(frames.len());
+        let background = (0..first_frame.data().len())
+            .map(|index| {
+                values.clear();
+                values.extend(frames.iter().map(|frame| frame.data()[index]));
+                let middle = values.len() / 2;
+                *values.select_nth_unstable(middle).1 as f32
+            })
+            .collect();
+        Some(MotionDetector {
+            background,
+            width: first_frame.width(),
+            height: first_frame.height(),
+            params,
+            alpha: None,
+        })
+    }
+
+    /// Sets up a detector for `model`, given a clip's frames (which may include its background
+    /// frame).  Returns `None` if the clip doesn't have what the model needs.
+    pub fn for_clip(
+        model: BackgroundModel,
+        frames: &[&CptvFrame],
+        params: MotionParams,
+    ) -> Option<MotionDetector> {
+        match model {
+            BackgroundModel::Stored => frames
+                .iter()
+                .find(|frame| frame.is_background_frame)
+                .map(|frame| MotionDetector::with_background(&frame.image_data, params)),
+            BackgroundModel::RunningAverage { alpha } => frames
+                .iter()
+                .find(|frame| !frame.is_background_frame)
+                .map(|frame| MotionDetector::with_running_average(&frame.image_data, alpha, params)),
+            BackgroundModel::Median => {
+                let clip_frames: Vec<&FrameData> = frames
+                    .iter()
+                    .filter(|frame| !frame.is_background_frame)
+                    .map(|frame| &frame.image_data)
+                    .collect();
+                MotionDetector::with_clip_median(&clip_frames, params)
+            }
+        }
+    }
+
+    /// The current background, in raw pixel units.
+    pub fn background(&self) -> &[f32] {
+        &self.background
+    }
+
+    pub fn params(&self) -> MotionParams {
+        self.params
+    }
+
+    /// Finds the foreground in `frame`, then updates a running average background.
+    pub fn process(&mut self, frame: &FrameData) -> MotionFrame {
+        assert_eq!(
+            (frame.width(), frame.height()),
+            (self.width, self.height),
+            "Frame dimensions don't match the background"
+        );
+        let threshold = self.params.threshold;
+        let mut mask: Vec<u8> = frame
+            .data()
+            .iter()
+            .zip(self.background.iter())
+            .map(|(value, background)| (*value as f32 - background > threshold) as u8)
+            .collect();
+        let foreground = mask.iter().filter(|pixel| **pixel != 0).count();
+        if let Some(alpha) = self.alpha {
+            for ((background, value), pixel) in self
+                .background
+                .iter_mut()
+                .zip(frame.data().iter())
+                .zip(mask.iter())
+            {
+                if *pixel == 0 {
+                    *background += (*value as f32 - *background) * alpha;
+                }
+            }
+        }
+        if self.params.dilation != 0 {
+            mask = dilate(&mask, self.width, self.height, self.params.dilation);
+        }
+        MotionFrame {
+            mask: ForegroundMask {
+                data: mask,
+                width: self.width,
+                height: self.height,
+            },
+            score: foreground as f32 / (self.width * self.height).max(1) as f32,
+        }
+    }
+}
+
+/// Motion for every frame of a clip, not including any background frame.  Returns `None` if the
+/// clip doesn't have what the background model needs.
+pub fn analyse_motion<'a, I: IntoIterator<Item = &'a CptvFrame>>(
+    frames: I,
+    model: BackgroundModel,
+    params: MotionParams,
+) -> Option<Vec<MotionFrame>> {
+    let frames: Vec<&CptvFrame> = frames.into_iter().collect();
+    let mut detector = MotionDetector::for_clip(model, &frames, params)?;
+    Some(
+        frames
+            .iter()
+            .filter(|frame| !frame.is_background_frame)
+            .map(|frame| detector.process(&frame.image_data))
+            .collect(),
+    )
+}
+
+// Grows the mask by `radius` pixels in each direction (a square structuring element), as
+// separate horizontal and vertical passes.
+fn dilate(mask: &[u8], width: usize, height: usize, radius: usize) -> Vec<u8> {
+    let mut rows = vec![0u8; mask.len()];
+    for y in 0..height {
+        let row = &mask[y * width..(y + 1) * width];
+        for x in 0..width {
+            let from = x.saturating_sub(radius);
+            let to = (x + radius + 1).min(width);
+            rows[y * width + x] = row[from..to].iter().copied().max().unwrap_or(0);
+        }
+    }
+    let mut dilated = vec![0u8; mask.len()];
+    for y in 0..height {
+        let from = y.saturating_sub(radius);
+        let to = (y + radius + 1).min(height);
+        for x in 0..width {
+            dilated[y * width + x] = (from..to).map(|y| rows[y * width + x]).max().unwrap_or(0);
+        }
+    }
+    dilated
+}
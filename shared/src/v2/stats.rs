@@ -0,0 +1,183 @@
+use crate::v2::types::CptvFrame;
+use serde::Serialize;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ClipStatsOptions {
+    /// Percentiles (0..100) either side of which values are treated as outliers when
+    /// recommending a display range.
+    pub low_percentile: f32,
+    pub high_percentile: f32,
+
+    pub histogram_bins: usize,
+
+    /// Frames this soon after an FFC are left out of the recommended display range.
+    pub ffc_settling: Duration,
+}
+
+impl Default for ClipStatsOptions {
+    fn default() -> Self {
+        ClipStatsOptions {
+            low_percentile: 0.1,
+            high_percentile: 99.9,
+            histogram_bins: 256,
+            ffc_settling: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FrameStats {
+    pub min: u16,
+    pub max: u16,
+    pub mean: f32,
+
+    // Whether the frame was captured while the sensor was settling after an FFC.
+    #[serde(rename = "ffcSettling")]
+    pub ffc_settling: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Histogram {
+    // Value at the start of the first bin.
+    pub start: u16,
+    #[serde(rename = "binWidth")]
+    pub bin_width: u32,
+    pub counts: Vec<u64>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ClipStats {
+    pub min: u16,
+    pub max: u16,
+
+    // Values at the low and high percentiles, over every pixel of every frame.
+    #[serde(rename = "lowPercentile")]
+    pub low_percentile: u16,
+    #[serde(rename = "highPercentile")]
+    pub high_percentile: u16,
+
+    pub histogram: Histogram,
+    pub frames: Vec<FrameStats>,
+
+    // Range to normalise frames to for display: the percentiles over the frames that aren't
+    // settling after an FFC (or over every frame, if they all are).
+    #[serde(rename = "displayMin")]
+    pub display_min: u16,
+    #[serde(rename = "displayMax")]
+    pub display_max: u16,
+}
+
+/// Gathers statistics over the frames of a clip as they are decoded.
+pub struct ClipStatsAccumulator {
+    options: ClipStatsOptions,
+    // Pixel counts for every possible value, for all frames and for stable frames only.
+    counts: Vec<u64>,
+    stable_counts: Vec<u64>,
+    frames: Vec<FrameStats>,
+}
+
+impl ClipStatsAccumulator {
+    pub fn new(options: ClipStatsOptions) -> ClipStatsAccumulator {
+        ClipStatsAccumulator {
+            options,
+            counts: vec![0; u16::MAX as usize + 1],
+            stable_counts: vec![0; u16::MAX as usize + 1],
+            frames: Vec::new(),
+        }
+    }
+
+    /// Adds a frame of the clip.  Background frames are ignored.
+    pub fn add_frame(&mut self, frame: &CptvFrame) {
+        if frame.is_background_frame {
+            return;
+        }
+        let ffc_settling = frame.is_ffc_settling(self.options.ffc_settling);
+        let pixels = frame.image_data.data();
+        let (mut min, mut max, mut total) = (u16::MAX, u16::MIN, 0u64);
+        for value in pixels {
+            min = min.min(*value);
+            max = max.max(*value);
+            total += *value as u64;
+            self.counts[*value as usize] += 1;
+            if !ffc_settling {
+                self.stable_counts[*value as usize] += 1;
+            }
+        }
+        self.frames.push(FrameStats {
+            min,
+            max,
+            mean: total as f32 / pixels.len().max(1) as f32,
+            ffc_settling,
+        });
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Statistics for the frames added so far, or `None` if there weren't any.
+    pub fn stats(&self) -> Option<ClipStats> {
+        let min = self.counts.iter().position(|count| *count != 0)? as u16;
+        let max = self.counts.iter().rposition(|count| *count != 0)? as u16;
+        let low = self.options.low_percentile;
+        let high = self.options.high_percentile;
+        let display_counts = if self.stable_counts.iter().any(|count| *count != 0) {
+            &self.stable_counts
+        } else {
+            &self.counts
+        };
+        Some(ClipStats {
+            min,
+            max,
+            low_percentile: percentile(&self.counts, low),
+            high_percentile: percentile(&self.counts, high),
+            histogram: self.histogram(min, max),
+            frames: self.frames.clone(),
+            display_min: percentile(display_counts, low),
+            display_max: percentile(display_counts, high),
+        })
+    }
+
+    fn histogram(&self, min: u16, max: u16) -> Histogram {
+        let bins = self.options.histogram_bins.max(1) as u32;
+        let range = max as u32 - min as u32 + 1;
+        let bin_width = range.div_ceil(bins);
+        let mut counts = vec![0; range.div_ceil(bin_width) as usize];
+        for (offset, count) in self.counts[min as usize..=max as usize].iter().enumerate() {
+            counts[offset / bin_width as usize] += count;
+        }
+        Histogram {
+            start: min,
+            bin_width,
+            counts,
+        }
+    }
+}
+
+/// Statistics for a whole clip.  Returns `None` if there are no frames other than a background
+/// frame.
+pub fn clip_stats<'a, I: IntoIterator<Item = &'a CptvFrame>>(
+    frames: I,
+    options: ClipStatsOptions,
+) -> Option<ClipStats> {
+    let mut accumulator = ClipStatsAccumulator::new(options);
+    for frame in frames {
+        accumulator.add_frame(frame);
+    }
+    accumulator.stats()
+}
+
+// The lowest value with at least `percentile`% of values at or below it.
+fn percentile(counts: &[u64], percentile: f32) -> u16 {
+    let total: u64 = counts.iter().sum();
+    let target = ((total as f64 * percentile.clamp(0.0, 100.0) as f64 / 100.0).ceil() as u64).max(1);
+    let mut seen = 0;
+    for (value, count) in counts.iter().enumerate() {
+        seen += count;
+        if seen >= target {
+            return value as u16;
+        }
+    }
+    u16::MAX
+}
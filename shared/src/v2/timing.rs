@@ -0,0 +1,169 @@
+use crate::v2::types::{Cptv2Header, CptvFrame};
+use serde::Serialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// An interval this far over the nominal frame interval (as a fraction of it) means at least one
+// frame went missing; this far under it is irregular.
+const DROPPED_FRAME_THRESHOLD: f32 = 0.5;
+
+/// Ties `time_on` (milliseconds since the camera powered on) to wall-clock time.  The header
+/// timestamp is the recording start, which corresponds to the first frame of the clip.
+#[derive(Debug, Clone, Copy)]
+pub struct ClipClock {
+    start_micros: u64,
+    start_time_on: u32,
+}
+
+impl ClipClock {
+    /// `first_frame` should be the first frame of the clip proper, not a background frame.
+    pub fn new(header: &Cptv2Header, first_frame: &CptvFrame) -> ClipClock {
+        ClipClock::with_start_time_on(header, first_frame.time_on)
+    }
+
+    pub fn with_start_time_on(header: &Cptv2Header, start_time_on: u32) -> ClipClock {
+        ClipClock {
+            start_micros: header.timestamp,
+            start_time_on,
+        }
+    }
+
+    /// Milliseconds since the start of the recording.  May be negative for frames (such as a
+    /// background frame) captured before the first frame.
+    pub fn offset_ms(&self, frame: &CptvFrame) -> i64 {
+        frame.time_on as i64 - self.start_time_on as i64
+    }
+
+    /// Absolute frame time in microseconds since the unix epoch.
+    pub fn timestamp_micros(&self, frame: &CptvFrame) -> u64 {
+        (self.start_micros as i64 + self.offset_ms(frame) * 1000).max(0) as u64
+    }
+
+    pub fn timestamp(&self, frame: &CptvFrame) -> SystemTime {
+        UNIX_EPOCH + Duration::from_micros(self.timestamp_micros(frame))
+    }
+}
+
+/// Absolute timestamps (microseconds since the unix epoch) for every frame in a clip.
+pub fn absolute_timestamps(header: &Cptv2Header, frames: &[CptvFrame]) -> Vec<u64> {
+    match frames.iter().find(|frame| !frame.is_background_frame) {
+        Some(first_frame) => {
+            let clock = ClipClock::new(header, first_frame);
+            frames
+                .iter()
+                .map(|frame| clock.timestamp_micros(frame))
+                .collect()
+        }
+        None => Vec::new(),
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FrameGap {
+    // Index of the frame after the gap.
+    #[serde(rename = "frameIndex")]
+    pub frame_index: usize,
+    #[serde(rename = "gapMs")]
+    pub gap_ms: u32,
+    #[serde(rename = "missingFrames")]
+    pub missing_frames: u32,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct TimingReport {
+    #[serde(rename = "expectedIntervalMs")]
+    pub expected_interval_ms: f32,
+    #[serde(rename = "meanIntervalMs")]
+    pub mean_interval_ms: f32,
+    #[serde(rename = "minIntervalMs")]
+    pub min_interval_ms: i64,
+    #[serde(rename = "maxIntervalMs")]
+    pub max_interval_ms: i64,
+
+    // Standard deviation from the expected interval, over intervals with no dropped frames.
+    #[serde(rename = "jitterMs")]
+    pub jitter_ms: f32,
+
+    pub intervals: usize,
+    #[serde(rename = "droppedFrames")]
+    pub dropped_frames: u32,
+    pub gaps: Vec<FrameGap>,
+
+    // Indices of frames with the same time_on as the previous frame.
+    #[serde(rename = "duplicatedTimestamps")]
+    pub duplicated_timestamps: Vec<usize>,
+
+    // Indices of frames with a time_on before the previous frame.
+    #[serde(rename = "outOfOrder")]
+    pub out_of_order: Vec<usize>,
+
+    // Intervals much shorter than expected.
+    #[serde(rename = "irregularIntervals")]
+    pub irregular_intervals: Vec<usize>,
+}
+
+impl TimingReport {
+    /// Intervals that don't match the declared frame rate, for any reason.
+    pub fn mismatched_intervals(&self) -> usize {
+        self.gaps.len()
+            + self.duplicated_timestamps.len()
+            + self.out_of_order.len()
+            + self.irregular_intervals.len()
+    }
+}
+
+/// Analyses the `time_on` deltas of consecutive frames (not including any background frame)
+/// against the frame rate declared in the header.
+pub fn analyse_timing<I: IntoIterator<Item = u32>>(times_on: I, fps: u8) -> TimingReport {
+    let mut report = TimingReport {
+        expected_interval_ms: if fps == 0 { 0.0 } else { 1000.0 / fps as f32 },
+        min_interval_ms: i64::MAX,
+        max_interval_ms: i64::MIN,
+        ..Default::default()
+    };
+    let expected = report.expected_interval_ms;
+    let mut total = 0i64;
+    let mut squared_error = 0.0f32;
+    let mut regular_intervals = 0;
+    let mut times_on = times_on.into_iter();
+    if let Some(mut prev) = times_on.next() {
+        for (index, time_on) in times_on.enumerate() {
+            let frame_index = index + 1;
+            let interval = time_on as i64 - prev as i64;
+            prev = time_on;
+            report.intervals += 1;
+            total += interval;
+            report.min_interval_ms = report.min_interval_ms.min(interval);
+            report.max_interval_ms = report.max_interval_ms.max(interval);
+            if interval == 0 {
+                report.duplicated_timestamps.push(frame_index);
+            } else if interval < 0 {
+                report.out_of_order.push(frame_index);
+            } else if fps == 0 {
+                continue;
+            } else if interval as f32 >= expected * (1.0 + DROPPED_FRAME_THRESHOLD) {
+                let missing_frames = ((interval as f32 / expected).round() as u32).max(2) - 1;
+                report.dropped_frames += missing_frames;
+                report.gaps.push(FrameGap {
+                    frame_index,
+                    gap_ms: interval as u32,
+                    missing_frames,
+                });
+            } else if (interval as f32) < expected * (1.0 - DROPPED_FRAME_THRESHOLD) {
+                report.irregular_intervals.push(frame_index);
+            } else {
+                squared_error += (interval as f32 - expected).powi(2);
+                regular_intervals += 1;
+            }
+        }
+    }
+    if report.intervals == 0 {
+        report.min_interval_ms = 0;
+        report.max_interval_ms = 0;
+        return report;
+    }
+    report.mean_interval_ms = total as f32 / report.intervals as f32;
+    if regular_intervals != 0 {
+        report.jitter_ms = (squared_error / regular_intervals as f32).sqrt();
+    }
+    report
+}
@@ -0,0 +1,293 @@
+use crate::v2::calibration::{reference_temp_c, Calibration};
+use crate::v2::motion::{analyse_motion, BackgroundModel, ForegroundMask, MotionParams};
+use crate::v2::types::CptvFrame;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy)]
+pub struct TrackerParams {
+    /// Regions with fewer pixels than this are ignored as noise.
+    pub min_mass: usize,
+
+    /// How far in pixels a region's centroid can be from a track's last position and still
+    /// be matched to it.
+    pub max_distance: f32,
+
+    /// How many frames a track can go without a matching region before it ends.
+    pub max_missing_frames: usize,
+}
+
+impl Default for TrackerParams {
+    fn default() -> Self {
+        TrackerParams {
+            min_mass: 4,
+            max_distance: 20.0,
+            max_missing_frames: 3,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// A connected region of foreground pixels in a single frame.
+#[derive(Serialize, Debug, Clone)]
+pub struct Region {
+    pub bounds: BoundingBox,
+    pub centroid: (f32, f32),
+
+    // Number of pixels in the region.
+    pub mass: usize,
+
+    // Hottest raw pixel value in the region, and where it is.
+    #[serde(rename = "peakValue")]
+    pub peak_value: u16,
+    #[serde(rename = "peakPosition")]
+    pub peak_position: (usize, usize),
+    #[serde(rename = "peakTempC")]
+    pub peak_temp_c: Option<f32>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TrackPoint {
+    #[serde(rename = "frameIndex")]
+    pub frame_index: usize,
+    #[serde(flatten)]
+    pub region: Region,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Track {
+    pub id: u32,
+    pub points: Vec<TrackPoint>,
+}
+
+impl Track {
+    pub fn start_frame(&self) -> usize {
+        self.points[0].frame_index
+    }
+
+    pub fn end_frame(&self) -> usize {
+        self.points[self.points.len() - 1].frame_index
+    }
+
+    pub fn last_point(&self) -> &TrackPoint {
+        &self.points[self.points.len() - 1]
+    }
+
+    pub fn point_at(&self, frame_index: usize) -> Option<&TrackPoint> {
+        self.points
+            .binary_search_by_key(&frame_index, |point| point.frame_index)
+            .ok()
+            .map(|index| &self.points[index])
+    }
+}
+
+/// Where a track is in a particular frame.
+#[derive(Serialize, Debug, Clone)]
+pub struct FrameTrack {
+    pub id: u32,
+    #[serde(flatten)]
+    pub point: TrackPoint,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct TrackingResult {
+    #[serde(rename = "frameCount")]
+    pub frame_count: usize,
+    pub tracks: Vec<Track>,
+}
+
+/// Labels the 8-connected foreground regions in a frame.
+pub fn find_regions(
+    frame: &CptvFrame,
+    mask: &ForegroundMask,
+    calibration: Option<&Calibration>,
+    min_mass: usize,
+) -> Vec<Region> {
+    let width = mask.width();
+    let height = mask.height();
+    let pixels = frame.image_data.data();
+    let reference_temp_c = reference_temp_c(frame);
+    let mut visited: Vec<bool> = mask.data().iter().map(|pixel| *pixel == 0).collect();
+    let mut regions = Vec::new();
+    let mut stack = Vec::new();
+    for start in 0..visited.len() {
+        if visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        stack.push(start);
+        let (mut left, mut top, mut right, mut bottom) = (width, height, 0, 0);
+        let (mut sum_x, mut sum_y, mut mass) = (0usize, 0usize, 0usize);
+        let (mut peak_value, mut peak_index) = (0u16, start);
+        while let Some(index) = stack.pop() {
+            let (x, y) = (index % width, index / width);
+            left = left.min(x);
+            right = right.max(x);
+            top = top.min(y);
+            bottom = bottom.max(y);
+            sum_x += x;
+            sum_y += y;
+            mass += 1;
+            if pixels[index] >= peak_value {
+                peak_value = pixels[index];
+                peak_index = index;
+            }
+            for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                    let neighbour = ny * width + nx;
+                    if !visited[neighbour] {
+                        visited[neighbour] = true;
+                        stack.push(neighbour);
+                    }
+                }
+            }
+        }
+        if mass < min_mass {
+            continue;
+        }
+        regions.push(Region {
+            bounds: BoundingBox {
+                x: left,
+                y: top,
+                width: right - left + 1,
+                height: bottom - top + 1,
+            },
+            centroid: (sum_x as f32 / mass as f32, sum_y as f32 / mass as f32),
+            mass,
+            peak_value,
+            peak_position: (peak_index % width, peak_index / width),
+            peak_temp_c: calibration
+                .map(|calibration| calibration.pixel_to_celsius(peak_value, reference_temp_c)),
+        });
+    }
+    regions
+}
+
+/// Follows foreground regions from frame to frame, matching each region to the nearest track.
+pub struct Tracker {
+    params: TrackerParams,
+    calibration: Option<Calibration>,
+    tracks: Vec<Track>,
+    // Indices into `tracks` of the tracks that can still be extended.
+    active: Vec<usize>,
+    frame_count: usize,
+}
+
+impl Tracker {
+    pub fn new(params: TrackerParams, calibration: Option<Calibration>) -> Tracker {
+        Tracker {
+            params,
+            calibration,
+            tracks: Vec::new(),
+            active: Vec::new(),
+            frame_count: 0,
+        }
+    }
+
+    /// Adds the next frame of the clip (not a background frame) along with its foreground mask.
+    pub fn process(&mut self, frame: &CptvFrame, mask: &ForegroundMask) {
+        let frame_index = self.frame_count;
+        self.frame_count += 1;
+        let regions = find_regions(frame, mask, self.calibration.as_ref(), self.params.min_mass);
+
+        // Greedily match the closest track/region pairs first.
+        let mut candidates = Vec::new();
+        for (active_index, track_index) in self.active.iter().enumerate() {
+            let (track_x, track_y) = self.tracks[*track_index].last_point().region.centroid;
+            for (region_index, region) in regions.iter().enumerate() {
+                let (x, y) = region.centroid;
+                let distance = ((x - track_x).powi(2) + (y - track_y).powi(2)).sqrt();
+                if distance <= self.params.max_distance {
+                    candidates.push((distance, active_index, region_index));
+                }
+            }
+        }
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut track_matched = vec![false; self.active.len()];
+        let mut region_matched = vec![false; regions.len()];
+        let mut matches = Vec::new();
+        for (_, active_index, region_index) in candidates {
+            if !track_matched[active_index] && !region_matched[region_index] {
+                track_matched[active_index] = true;
+                region_matched[region_index] = true;
+                matches.push((self.active[active_index], region_index));
+            }
+        }
+
+        let mut regions: Vec<Option<Region>> = regions.into_iter().map(Some).collect();
+        for (track_index, region_index) in matches {
+            self.tracks[track_index].points.push(TrackPoint {
+                frame_index,
+                region: regions[region_index].take().unwrap(),
+            });
+        }
+        let max_missing_frames = self.params.max_missing_frames;
+        let tracks = &self.tracks;
+        self.active
+            .retain(|track_index| frame_index - tracks[*track_index].end_frame() <= max_missing_frames);
+        for region in regions.into_iter().flatten() {
+            self.active.push(self.tracks.len());
+            self.tracks.push(Track {
+                id: self.tracks.len() as u32 + 1,
+                points: vec![TrackPoint {
+                    frame_index,
+                    region,
+                }],
+            });
+        }
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    /// Tracks with a region in the given frame.
+    pub fn tracks_at(&self, frame_index: usize) -> impl Iterator<Item = FrameTrack> + '_ {
+        self.tracks.iter().filter_map(move |track| {
+            track.point_at(frame_index).map(|point| FrameTrack {
+                id: track.id,
+                point: point.clone(),
+            })
+        })
+    }
+
+    /// Number of frames processed so far.
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    pub fn finish(self) -> TrackingResult {
+        TrackingResult {
+            frame_count: self.frame_count,
+            tracks: self.tracks,
+        }
+    }
+}
+
+/// Finds the foreground in every frame of a clip and tracks it.  Returns `None` if the clip
+/// doesn't have what the background model needs.
+pub fn track_clip<'a, I: IntoIterator<Item = &'a CptvFrame>>(
+    frames: I,
+    model: BackgroundModel,
+    motion_params: MotionParams,
+    tracker_params: TrackerParams,
+    calibration: Option<Calibration>,
+) -> Option<TrackingResult> {
+    let frames: Vec<&CptvFrame> = frames.into_iter().collect();
+    let motion = analyse_motion(frames.iter().copied(), model, motion_params)?;
+    let mut tracker = Tracker::new(tracker_params, calibration);
+    for (frame, motion) in frames
+        .iter()
+        .filter(|frame| !frame.is_background_frame)
+        .zip(motion.iter())
+    {
+        tracker.process(frame, &motion.mask);
+    }
+    Some(tracker.finish())
+}
@@ -1,12 +1,12 @@
 use core::fmt;
 #[allow(unused)]
 use log::{info, trace, warn};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::fmt::{Debug, Formatter};
 use std::ops::{Index, IndexMut};
 use std::time::Duration;
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Cptv2Header {
     pub timestamp: u64,
     pub width: u32,
@@ -34,7 +34,7 @@ pub struct Cptv2Header {
     pub loc_timestamp: Option<u64>,
     pub altitude: Option<f32>,
     pub accuracy: Option<f32>,
-    #[serde(rename = "hasBackgroundFrame")]
+    #[serde(rename = "hasBackgroundFrame", default)]
     pub has_background_frame: bool,
 
     #[serde(rename = "totalFrames")]
@@ -45,6 +45,12 @@ pub struct Cptv2Header {
     pub max_value: Option<u16>,
 }
 
+impl Default for Cptv2Header {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Cptv2Header {
     pub fn new() -> Cptv2Header {
         // NOTE: Set default values for things not included in
@@ -77,6 +83,89 @@ impl Cptv2Header {
     }
 }
 
+/// Number of rows or columns at each edge of the frame.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct EdgeBorder {
+    pub top: usize,
+    pub right: usize,
+    pub bottom: usize,
+    pub left: usize,
+}
+
+impl EdgeBorder {
+    pub fn uniform(size: usize) -> EdgeBorder {
+        EdgeBorder {
+            top: size,
+            right: size,
+            bottom: size,
+            left: size,
+        }
+    }
+}
+
+/// Pixels to leave out when tracking the min and max of a frame, for sensors whose edge pixels
+/// are unreliable.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MinMaxExclusion {
+    pub border: EdgeBorder,
+
+    /// Pixels to ignore, in row order.  Must be `width * height` long to be used.
+    pub mask: Option<Vec<bool>>,
+}
+
+impl MinMaxExclusion {
+    pub fn border(size: usize) -> MinMaxExclusion {
+        MinMaxExclusion {
+            border: EdgeBorder::uniform(size),
+            mask: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.border == EdgeBorder::default() && self.mask.is_none()
+    }
+}
+
+/// Which `MinMaxExclusion` to use for each camera model.
+#[derive(Debug, Clone, Default)]
+pub struct ModelExclusions {
+    default: MinMaxExclusion,
+    by_model: Vec<(String, MinMaxExclusion)>,
+}
+
+impl ModelExclusions {
+    /// Used for models without their own exclusion.
+    pub fn set_default(&mut self, exclusion: MinMaxExclusion) {
+        self.default = exclusion;
+    }
+
+    /// Model names are matched case insensitively against the header `model`.
+    pub fn set_for_model(&mut self, model: &str, exclusion: MinMaxExclusion) {
+        let model = model.to_lowercase();
+        self.by_model.retain(|(existing, _)| *existing != model);
+        self.by_model.push((model, exclusion));
+    }
+
+    pub fn for_header(&self, header: &Cptv2Header) -> &MinMaxExclusion {
+        let model = header.model.as_deref().map(str::to_lowercase);
+        self.by_model
+            .iter()
+            .find(|(existing, _)| Some(existing) == model.as_ref())
+            .map(|(_, exclusion)| exclusion)
+            .unwrap_or(&self.default)
+    }
+}
+
+/// What was left out of a frame's min and max.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ExcludedPixels {
+    pub border: EdgeBorder,
+    #[serde(rename = "maskedPixels")]
+    pub masked_pixels: usize,
+}
+
+/// Serialized as frame metadata, without the pixels, which are passed around separately.
+/// `json::ClipJson` is the form of a clip that includes them.
 #[derive(Serialize, Clone)]
 pub struct FrameData {
     #[serde(skip_serializing)]
@@ -85,6 +174,8 @@ pub struct FrameData {
     height: usize,
     min: u16,
     max: u16,
+    #[serde(rename = "minMaxExclusion", skip_serializing_if = "Option::is_none")]
+    excluded: Option<ExcludedPixels>,
 }
 
 impl FrameData {
@@ -95,21 +186,21 @@ impl FrameData {
             height,
             min: u16::MAX,
             max: u16::MIN,
+            excluded: None,
         }
     }
 
     pub fn with_dimensions_and_data(width: usize, height: usize, data: &[u16]) -> FrameData {
-        let frame_range = data.iter().fold(u16::MAX..u16::MIN, |mut acc, &x| {
-            acc.start = u16::min(acc.start, x);
-            acc.end = u16::max(acc.end, x);
-            acc
-        });
+        let (min, max) = data
+            .iter()
+            .fold((u16::MAX, u16::MIN), |(min, max), &x| (min.min(x), max.max(x)));
         FrameData {
             data: Vec::from(data),
             width,
             height,
-            min: frame_range.start,
-            max: frame_range.end,
+            min,
+            max,
+            excluded: None,
         }
     }
 
@@ -125,13 +216,63 @@ impl FrameData {
         &self.data
     }
 
+    /// Sets a pixel, keeping track of the min and max.  Use `exclude_from_range` afterwards to
+    /// leave unreliable pixels out of the min and max.
     pub fn set(&mut self, x: usize, y: usize, val: u16) {
-        // Ignore edge pixels for this?
         self.max = u16::max(self.max, val);
         self.min = u16::min(self.min, val);
         self[y][x] = val;
     }
 
+    pub fn min(&self) -> u16 {
+        self.min
+    }
+
+    pub fn max(&self) -> u16 {
+        self.max
+    }
+
+    pub fn excluded(&self) -> Option<ExcludedPixels> {
+        self.excluded
+    }
+
+    /// Recomputes the min and max, leaving out the excluded pixels.  A mask that doesn't match
+    /// the frame dimensions is ignored.  If everything is excluded, the min and max are left as
+    /// they were.
+    pub fn exclude_from_range(&mut self, exclusion: &MinMaxExclusion) {
+        if exclusion.is_empty() {
+            return;
+        }
+        let mask = exclusion
+            .mask
+            .as_deref()
+            .filter(|mask| mask.len() == self.data.len());
+        let border = exclusion.border;
+        let right = self.width.saturating_sub(border.right);
+        let bottom = self.height.saturating_sub(border.bottom);
+        let (mut min, mut max) = (u16::MAX, u16::MIN);
+        let mut included = false;
+        for y in border.top..bottom {
+            for x in border.left..right {
+                let index = y * self.width + x;
+                if mask.is_some_and(|mask| mask[index]) {
+                    continue;
+                }
+                min = min.min(self.data[index]);
+                max = max.max(self.data[index]);
+                included = true;
+            }
+        }
+        if included {
+            self.min = min;
+            self.max = max;
+        }
+        self.excluded = Some(ExcludedPixels {
+            border,
+            masked_pixels: mask.map_or(0, |mask| mask.iter().filter(|masked| **masked).count()),
+        });
+    }
+
     pub fn as_slice(&self) -> &[u8] {
         unsafe {
             std::slice::from_raw_parts(
@@ -194,7 +335,7 @@ impl Iterator for SnakingIterator {
             let output = y * self.stride + self.x as usize; //unsafe { *self.data.data.get_unchecked(y * self.stride + self.x as usize) };
             //dbg!(self.x);
             //dbg!((1isize + ((y & 1) as isize * -2)) as isize);
-            self.x += (1isize + ((y & 1) as isize * -2)) as isize;
+            self.x += 1isize + ((y & 1) as isize * -2);
             assert!(self.x >= 0);
             assert!(self.x < self.stride as isize);
             //dbg!(self.x);
@@ -224,15 +365,48 @@ impl IndexMut<usize> for FrameData {
     }
 }
 
+/// How each pixel of a frame is predicted from its neighbours, after subtracting the previous
+/// frame.  Only the differences from the prediction are stored.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SpatialPredictor {
+    /// The previous pixel, in a scan that reverses direction on every other row.  Frames
+    /// without a predictor field use this.
+    #[default]
+    Snake = 0,
+
+    /// The median edge detector from LOCO-I, which picks between the left and above pixels,
+    /// or a plane through them and the pixel above left.  Pixels are stored in row order.  It
+    /// usually compresses noisy or busy scenes better.
+    Med = 1,
+}
+
+impl SpatialPredictor {
+    pub fn from_u8(val: u8) -> Option<SpatialPredictor> {
+        match val {
+            0 => Some(SpatialPredictor::Snake),
+            1 => Some(SpatialPredictor::Med),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Serialize, Clone)]
 pub struct CptvFrame {
     #[serde(rename = "timeOnMs")]
     pub time_on: u32,
 
-    #[serde(skip_serializing)]
+    // Details of how the frame was packed, which only matter while decoding it.
+    #[serde(skip)]
     pub bit_width: u8,
-    #[serde(skip_serializing)]
+    #[serde(skip)]
     pub frame_size: u32,
+    #[serde(skip)]
+    pub predictor: SpatialPredictor,
+    /// Set on frames encoded on their own rather than against the frame before, so decoding can
+    /// start afresh from them.  The first frame of a clip is always encoded on its own.
+    #[serde(skip)]
+    pub is_intra_frame: bool,
 
     // Some cameras may not have FFC information, so this is optional.
     #[serde(rename = "lastFfcTimeMs")]
@@ -245,6 +419,12 @@ pub struct CptvFrame {
     #[serde(rename = "isBackgroundFrame")]
     pub is_background_frame: bool,
 
+    /// Set when corrupted data was skipped before this frame in recovery mode, and there was no
+    /// intra frame since.  Frames are delta encoded against the frame before, which was lost,
+    /// so the pixels are off by however much they changed over the lost frames.
+    #[serde(rename = "decodedAfterSkip")]
+    pub decoded_after_skip: bool,
+
     // Raw image data?
     #[serde(rename = "imageData")]
     pub image_data: FrameData,
@@ -256,13 +436,38 @@ impl CptvFrame {
             time_on: 0,
             bit_width: 0,
             frame_size: 0,
+            predictor: SpatialPredictor::Snake,
+            is_intra_frame: false,
             last_ffc_time: None,
             last_ffc_temp_c: None,
             frame_temp_c: None,
             is_background_frame: false,
+            decoded_after_skip: false,
             image_data: FrameData::with_dimensions(width, height),
         }
     }
+
+    /// Time since the last flat field correction, for cameras that report it.
+    pub fn time_since_ffc(&self) -> Option<Duration> {
+        self.last_ffc_time.map(|last_ffc_time| {
+            Duration::from_millis(self.time_on.saturating_sub(last_ffc_time) as u64)
+        })
+    }
+
+    /// Approximate number of frames captured since the last flat field correction.
+    pub fn frames_since_ffc(&self, fps: u8) -> Option<u32> {
+        self.time_since_ffc()
+            .map(|elapsed| (elapsed.as_millis() as u64 * fps as u64 / 1000) as u32)
+    }
+
+    /// Frames shortly after an FFC are unstable while the sensor settles, so tracking code may
+    /// want to ignore them.  Frames without FFC information are never considered settling.
+    pub fn is_ffc_settling(&self, threshold: Duration) -> bool {
+        match self.time_since_ffc() {
+            Some(elapsed) => elapsed < threshold,
+            None => false,
+        }
+    }
 }
 
 impl Debug for CptvFrame {
@@ -271,11 +476,8 @@ impl Debug for CptvFrame {
             .field(
                 "last_ffc_time",
                 // To get absolute time, need recording start time from header:
-                &match self.last_ffc_time {
-                    Some(timestamp) => format!(
-                        "{:?}s ago",
-                        &Duration::from_millis(self.time_on as u64 - timestamp as u64).as_secs()
-                    ),
+                &match self.time_since_ffc() {
+                    Some(elapsed) => format!("{:?}s ago", elapsed.as_secs()),
                     None => "None".to_string(),
                 },
             )
@@ -297,7 +499,9 @@ impl Debug for CptvFrame {
             .field("frame_temp_c", &self.frame_temp_c)
             .field("last_ffc_temp_c", &self.last_ffc_temp_c)
             .field("bit_width", &self.bit_width)
+            .field("predictor", &self.predictor)
             .field("is_background_frame", &self.is_background_frame)
+            .field("decoded_after_skip", &self.decoded_after_skip)
             .field(
                 "image_data",
                 &format!(
@@ -309,6 +513,47 @@ impl Debug for CptvFrame {
     }
 }
 
+/// A whole decoded clip, for tools that rewrite recordings.  Serializes to, and deserializes
+/// from, the JSON form described by `json::ClipJson`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "crate::v2::json::ClipJson")]
+pub struct Clip {
+    pub header: Cptv2Header,
+    pub background: Option<CptvFrame>,
+
+    // Frames of the clip, not including the background frame.
+    pub frames: Vec<CptvFrame>,
+}
+
+impl Clip {
+    /// Sets `min_value` and `max_value` in the header to the range of pixel values in the frames,
+    /// not including the background frame, for tools that change the pixels.
+    pub fn update_value_range(&mut self) {
+        let mut values = self
+            .frames
+            .iter()
+            .flat_map(|frame| frame.image_data.data().iter().copied());
+        let first = values.next();
+        let (min, max) = values.fold((first, first), |(min, max), value| {
+            (min.min(Some(value)), max.max(Some(value)))
+        });
+        self.header.min_value = min;
+        self.header.max_value = max;
+    }
+}
+
+/// A run of bytes in the decompressed stream that was skipped while resynchronising after
+/// corrupted data.
+#[derive(Serialize, Debug, Clone)]
+pub struct SkippedRange {
+    pub offset: usize,
+    pub length: usize,
+
+    // Index of the first frame decoded after the skipped bytes.
+    #[serde(rename = "frameIndex")]
+    pub frame_index: usize,
+}
+
 #[repr(u8)]
 #[derive(PartialEq, Debug)]
 pub enum FieldType {
@@ -347,6 +592,8 @@ pub enum FieldType {
     FrameTempC = b'a',
     LastFfcTempC = b'b',
     TimeOn = b't',
+    Predictor = b'p',
+    IntraFrame = b'i',
     Unknown = b';',
 }
 
@@ -385,6 +632,8 @@ impl From<char> for FieldType {
             't' => TimeOn,
             'a' => FrameTempC,
             'b' => LastFfcTempC,
+            'p' => Predictor,
+            'i' => IntraFrame,
             _ => Unknown,
         }
     }
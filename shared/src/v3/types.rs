@@ -12,6 +12,12 @@ pub struct Cptv3Header {
     pub frames_per_iframe: u8,
 }
 
+impl Default for Cptv3Header {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Cptv3Header {
     #[allow(unused)]
     pub fn new() -> Cptv3Header {